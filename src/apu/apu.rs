@@ -0,0 +1,367 @@
+use super::{noise::Noise, square::Square, wave::Wave};
+
+/*
+
+Audio Processing Unit
+
+0xFF10 - 0xFF14: Channel 1, square with sweep
+0xFF16 - 0xFF19: Channel 2, square
+0xFF1A - 0xFF1E: Channel 3, wave
+0xFF20 - 0xFF23: Channel 4, noise
+0xFF24: NR50 - Bit 6-4: Left volume
+               Bit 2-0: Right volume
+0xFF25: NR51 - Bit 7-4: Channel 4-1 to the left output
+               Bit 3-0: Channel 4-1 to the right output
+0xFF26: NR52 - Bit 7: Power
+               Bit 3-0: Channel 4-1 enabled (read only)
+0xFF30 - 0xFF3F: Wave RAM
+
+The frame sequencer steps at 512 Hz on the falling edge of DIV bit 4 and
+clocks the length counters (256 Hz), the sweep (128 Hz) and the envelopes
+(64 Hz):
+
+Step:     0  1  2  3  4  5  6  7
+Length:   x     x     x     x
+Sweep:          x           x
+Envelope:                      x
+
+*/
+
+pub const NR10_ADDR: u16 = 0xFF10;
+pub const NR52_ADDR: u16 = 0xFF26;
+pub const WAVE_RAM_ADDR: u16 = 0xFF30;
+pub const APU_END_ADDR: u16 = 0xFF3F;
+
+const NR50_ADDR: u16 = 0xFF24;
+const NR51_ADDR: u16 = 0xFF25;
+
+// T-cycles per second
+pub const CLOCK_RATE: u32 = 4_194_304;
+// One sample per m-cycle, for output backends to resample from
+pub const DEFAULT_SAMPLE_RATE: u32 = CLOCK_RATE / 4;
+
+// Bits that read back as 1, indexed from NR10
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10 - NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20 - NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30 - NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40 - NR44
+    0x00, 0x00, 0x70, // NR50 - NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+#[derive(Debug)]
+pub struct APU {
+    powered: bool,
+    // Last value written to each register, indexed from NR10
+    registers: [u8; 0x20],
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    // Next frame sequencer step
+    frame_step: u8,
+    sample_rate: u32,
+    // Counts up by the sample rate every t-cycle; a sample is due each time
+    // it passes the clock rate
+    sample_clock: u64,
+    // High-pass filter state, standing in for the output capacitors
+    capacitor: [f32; 2],
+    charge_factor: f32,
+    samples: Vec<[f32; 2]>,
+    // Each channel on its own, before panning and master volume; only
+    // collected while stems are being recorded
+    stems: Option<Vec<[f32; 4]>>,
+    stem_capacitors: [f32; 4],
+}
+
+impl APU {
+    // `sample_rate` is the number of stereo samples produced per emulated
+    // second
+    pub fn new(sample_rate: u32) -> Self {
+        APU {
+            powered: false,
+            registers: [0; 0x20],
+            square1: Square::new(true),
+            square2: Square::new(false),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            frame_step: 0,
+            sample_rate,
+            sample_clock: 0,
+            capacitor: [0.0; 2],
+            charge_factor: 0.999958f32.powf(CLOCK_RATE as f32 / sample_rate as f32),
+            samples: vec![],
+            stems: None,
+            stem_capacitors: [0.0; 4],
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            WAVE_RAM_ADDR..=APU_END_ADDR => self.wave.ram[(addr - WAVE_RAM_ADDR) as usize],
+            NR52_ADDR => {
+                let channels = [
+                    self.square1.enabled,
+                    self.square2.enabled,
+                    self.wave.enabled,
+                    self.noise.enabled,
+                ];
+                let status = channels
+                    .iter()
+                    .enumerate()
+                    .fold(0, |x, (i, enabled)| x | ((*enabled as u8) << i));
+                ((self.powered as u8) << 7) | 0x70 | status
+            }
+            _ => {
+                let register = (addr - NR10_ADDR) as usize;
+                self.registers[register] | READ_MASKS[register]
+            }
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        if let WAVE_RAM_ADDR..=APU_END_ADDR = addr {
+            self.wave.ram[(addr - WAVE_RAM_ADDR) as usize] = value;
+            return;
+        }
+        if addr == NR52_ADDR {
+            self.write_power(value & 0x80 != 0);
+            return;
+        }
+        if !self.powered {
+            // Length counters can still be loaded while the APU is off
+            match addr {
+                0xFF11 => self.square1.length.load(value & 0x3F),
+                0xFF16 => self.square2.length.load(value & 0x3F),
+                0xFF1B => self.wave.length.load(value),
+                0xFF20 => self.noise.length.load(value & 0x3F),
+                _ => {}
+            }
+            return;
+        }
+
+        self.registers[(addr - NR10_ADDR) as usize] = value;
+        // Enabling a length counter when the next step doesn't clock lengths
+        // clocks it once straight away
+        let extra_clock = self.frame_step % 2 == 1;
+        match addr {
+            0xFF10..=0xFF14 => self.square1.write(addr - 0xFF10, value, extra_clock),
+            0xFF15..=0xFF19 => self.square2.write(addr - 0xFF15, value, extra_clock),
+            0xFF1A..=0xFF1E => self.wave.write(addr - 0xFF1A, value, extra_clock),
+            0xFF1F..=0xFF23 => self.noise.write(addr - 0xFF1F, value, extra_clock),
+            _ => {}
+        }
+    }
+
+    fn write_power(&mut self, powered: bool) {
+        match (self.powered, powered) {
+            (true, false) => {
+                self.registers = [0; 0x20];
+                self.square1.power_off();
+                self.square2.power_off();
+                self.wave.power_off();
+                self.noise.power_off();
+            }
+            (false, true) => self.frame_step = 0,
+            _ => {}
+        }
+        self.powered = powered;
+    }
+
+    // Called on the falling edge of DIV bit 4
+    pub fn step_frame_sequencer(&mut self) {
+        if !self.powered {
+            return;
+        }
+        if self.frame_step.is_multiple_of(2) {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.square1.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    pub fn tick(&mut self, t: u8) {
+        if self.powered {
+            self.square1.tick(t as u32);
+            self.square2.tick(t as u32);
+            self.wave.tick(t as u32);
+            self.noise.tick(t as u32);
+        }
+
+        self.sample_clock += t as u64 * self.sample_rate as u64;
+        while self.sample_clock >= CLOCK_RATE as u64 {
+            self.sample_clock -= CLOCK_RATE as u64;
+            let sample = self.mix();
+            self.samples.push(sample);
+            if self.stems.is_some() {
+                let stems = self.mix_stems();
+                self.stems.as_mut().unwrap().push(stems);
+            }
+        }
+    }
+
+    pub fn record_stems(&mut self, enabled: bool) {
+        self.stems = enabled.then(Vec::new);
+        self.stem_capacitors = [0.0; 4];
+    }
+
+    // Stem samples produced since the last call, one per sample from
+    // `take_samples`; empty unless stems are being recorded
+    pub fn take_stems(&mut self) -> Vec<[f32; 4]> {
+        self.stems.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    // Samples produced since the last call, as [left, right] in -1.0 - 1.0
+    pub fn take_samples(&mut self) -> Vec<[f32; 2]> {
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn buffered_samples(&self) -> usize {
+        self.samples.len()
+    }
+
+    // Analog output of each channel, -1.0 - 1.0
+    fn channel_outputs(&self) -> [f32; 4] {
+        let dac = |digital: u8, enabled: bool| match enabled {
+            true => digital as f32 / 7.5 - 1.0,
+            false => 0.0,
+        };
+        [
+            dac(self.square1.output(), self.square1.dac_enabled),
+            dac(self.square2.output(), self.square2.dac_enabled),
+            dac(self.wave.output(), self.wave.dac_enabled),
+            dac(self.noise.output(), self.noise.dac_enabled),
+        ]
+    }
+
+    fn mix(&mut self) -> [f32; 2] {
+        if !self.powered {
+            return [0.0; 2];
+        }
+        let outputs = self.channel_outputs();
+        let nr50 = self.registers[(NR50_ADDR - NR10_ADDR) as usize];
+        let nr51 = self.registers[(NR51_ADDR - NR10_ADDR) as usize];
+
+        let mut sample = [0.0; 2];
+        // Left uses the upper nibble of NR51 and bits 6-4 of NR50
+        for (side, shift) in [(0, 4), (1, 0)] {
+            let mixed: f32 = (0..4)
+                .filter(|i| (nr51 >> (shift + i)) & 1 == 1)
+                .map(|i| outputs[i])
+                .sum();
+            let volume = ((nr50 >> shift) & 0x07) as f32 + 1.0;
+            let input = mixed / 4.0 * volume / 8.0;
+
+            sample[side] = high_pass(&mut self.capacitor[side], input, self.charge_factor);
+        }
+        sample
+    }
+
+    // Scaled as each channel contributes to the mix at full volume
+    fn mix_stems(&mut self) -> [f32; 4] {
+        if !self.powered {
+            return [0.0; 4];
+        }
+        let outputs = self.channel_outputs();
+        let mut stems = [0.0; 4];
+        for (i, stem) in stems.iter_mut().enumerate() {
+            let capacitor = &mut self.stem_capacitors[i];
+            *stem = high_pass(capacitor, outputs[i] / 4.0, self.charge_factor);
+        }
+        stems
+    }
+}
+
+fn high_pass(capacitor: &mut f32, input: f32, charge_factor: f32) -> f32 {
+    let output = input - *capacitor;
+    *capacitor = input - output * charge_factor;
+    output
+}
+
+#[cfg(test)]
+fn create_apu() -> APU {
+    let mut apu = APU::new(CLOCK_RATE / 4);
+    apu.write(NR52_ADDR, 0x80);
+    apu
+}
+
+#[test]
+fn test_apu_power() {
+    let mut apu = create_apu();
+    apu.write(0xFF12, 0xF0);
+    apu.write(0xFF14, 0x80);
+    apu.write(0xFF24, 0x77);
+    assert_eq!(apu.read(NR52_ADDR), 0xF1);
+    assert_eq!(apu.read(0xFF24), 0x77);
+    // Write-only bits read back as 1
+    assert_eq!(apu.read(0xFF14), 0xBF);
+
+    apu.write(WAVE_RAM_ADDR, 0x12);
+    apu.write(NR52_ADDR, 0x00);
+    assert_eq!(apu.read(NR52_ADDR), 0x70);
+    assert_eq!(apu.read(0xFF24), 0x00);
+    // Registers ignore writes while the APU is off, wave RAM doesn't
+    apu.write(0xFF24, 0x77);
+    assert_eq!(apu.read(0xFF24), 0x00);
+    assert_eq!(apu.read(WAVE_RAM_ADDR), 0x12);
+}
+
+#[test]
+fn test_frame_sequencer_length() {
+    let mut apu = create_apu();
+    apu.write(0xFF17, 0xF0);
+    // Length of 2 with the length counter enabled
+    apu.write(0xFF16, 0x3E);
+    apu.write(0xFF19, 0xC0);
+    assert_eq!(apu.read(NR52_ADDR) & 0x02, 0x02);
+
+    // Steps 0 and 2 clock lengths, 1 doesn't
+    apu.step_frame_sequencer();
+    apu.step_frame_sequencer();
+    assert_eq!(apu.read(NR52_ADDR) & 0x02, 0x02);
+    apu.step_frame_sequencer();
+    assert_eq!(apu.read(NR52_ADDR) & 0x02, 0x00);
+}
+
+#[test]
+fn test_apu_samples() {
+    let mut apu = create_apu();
+    // Channel 2, 50% duty at full volume on both sides
+    apu.write(0xFF24, 0x77);
+    apu.write(0xFF25, 0x22);
+    apu.write(0xFF16, 0x80);
+    apu.write(0xFF17, 0xF0);
+    apu.write(0xFF18, 0xF0);
+    apu.write(0xFF19, 0x87);
+    apu.record_stems(true);
+    for _ in 0..256 {
+        apu.tick(4);
+    }
+
+    let samples = apu.take_samples();
+    assert_eq!(samples.len(), 256);
+    assert!(samples.iter().any(|[left, _]| *left > 0.1));
+    assert!(samples.iter().all(|[left, right]| left == right));
+    assert_eq!(apu.buffered_samples(), 0);
+
+    // Only channel 2 is playing
+    let stems = apu.take_stems();
+    assert_eq!(stems.len(), 256);
+    assert!(stems.iter().all(|stem| stem[0] == 0.0 && stem[3] == 0.0));
+    assert!(stems.iter().any(|stem| stem[1] > 0.1));
+}
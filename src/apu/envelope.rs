@@ -0,0 +1,71 @@
+/*
+
+Volume envelope (NRx2)
+
+Bit 7-4: Initial volume
+Bit 3: Direction (1=Increase)
+Bit 2-0: Period in 64 Hz steps (0=Disabled)
+
+*/
+
+#[derive(Debug, Clone, Default)]
+pub struct Envelope {
+    initial: u8,
+    increase: bool,
+    period: u8,
+    timer: u8,
+    pub volume: u8,
+}
+
+impl Envelope {
+    pub fn write(&mut self, value: u8) {
+        self.initial = value >> 4;
+        self.increase = value & 0x08 != 0;
+        self.period = value & 0x07;
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = self.reload();
+    }
+
+    pub fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.reload();
+            match self.increase {
+                true if self.volume < 15 => self.volume += 1,
+                false if self.volume > 0 => self.volume -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    // A period of 0 reloads the timer with 8
+    fn reload(&self) -> u8 {
+        match self.period {
+            0 => 8,
+            period => period,
+        }
+    }
+}
+
+#[test]
+fn test_envelope() {
+    let mut envelope = Envelope::default();
+    envelope.write(0x22);
+    envelope.trigger();
+    envelope.clock();
+    assert_eq!(envelope.volume, 2);
+    envelope.clock();
+    assert_eq!(envelope.volume, 1);
+
+    // Increasing stops at 15
+    envelope.write(0xF9);
+    envelope.trigger();
+    envelope.clock();
+    assert_eq!(envelope.volume, 15);
+}
@@ -0,0 +1,75 @@
+/*
+
+Length counter
+
+Counts down at 256 Hz while enabled and switches the channel off when it
+reaches 0. The NRx1 register loads `max - value`; triggering a channel with an
+expired counter reloads it with `max`.
+
+Enabling the counter, or triggering with it enabled, while the frame sequencer's
+next step doesn't clock lengths gives it one extra clock.
+
+*/
+
+#[derive(Debug, Clone)]
+pub struct LengthCounter {
+    max: u16,
+    counter: u16,
+    pub enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> Self {
+        LengthCounter {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    pub fn load(&mut self, value: u8) {
+        self.counter = self.max - value as u16;
+    }
+
+    // Returns true if the channel should be switched off
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+
+    // Handles an NRx4 write; `extra_clock` is set when the frame sequencer's
+    // next step doesn't clock lengths. Returns true if the channel should be
+    // switched off
+    pub fn write(&mut self, enabled: bool, trigger: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enabled;
+        let mut expired = false;
+        if extra_clock && !was_enabled && enabled {
+            expired = self.clock();
+        }
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if enabled && extra_clock {
+                self.counter -= 1;
+            }
+        }
+        expired && !trigger
+    }
+}
+
+#[test]
+fn test_length_counter() {
+    let mut length = LengthCounter::new(64);
+    length.load(62);
+    assert!(!length.write(true, false, false));
+    assert!(!length.clock());
+    assert!(length.clock());
+    assert!(!length.clock());
+
+    // Triggering an expired counter reloads it, minus the extra clock
+    assert!(!length.write(true, true, true));
+    assert_eq!(length.counter, 63);
+}
@@ -0,0 +1,6 @@
+pub mod apu;
+pub mod envelope;
+pub mod length;
+pub mod noise;
+pub mod square;
+pub mod wave;
@@ -0,0 +1,148 @@
+use super::{envelope::Envelope, length::LengthCounter};
+
+/*
+
+Noise channel (NR41 - NR44)
+
+NR41: Bit 5-0: Length (64-n)
+NR42: Envelope
+NR43: Bit 7-4: Clock shift
+      Bit 3: LFSR width (1=7 bits)
+      Bit 2-0: Divisor code
+NR44: Bit 7: Trigger
+      Bit 6: Length enable
+
+The LFSR shifts every divisor << shift t-cycles; the output is the inverse
+of bit 0.
+
+*/
+
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+#[derive(Debug, Clone)]
+pub struct Noise {
+    pub enabled: bool,
+    pub dac_enabled: bool,
+    shift: u8,
+    short_mode: bool,
+    divisor_code: u8,
+    timer: i32,
+    lfsr: u16,
+    pub length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl Noise {
+    pub(crate) fn new() -> Self {
+        Noise {
+            enabled: false,
+            dac_enabled: false,
+            shift: 0,
+            short_mode: false,
+            divisor_code: 0,
+            timer: 0,
+            lfsr: 0x7FFF,
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+        }
+    }
+
+    // Everything but the length counter is cleared when the APU powers off
+    pub fn power_off(&mut self) {
+        let mut length = self.length.clone();
+        length.enabled = false;
+        *self = Noise {
+            length,
+            ..Noise::new()
+        };
+    }
+
+    // `register` is the offset from NR40 (NR41 is 1)
+    pub fn write(&mut self, register: u16, value: u8, extra_clock: bool) {
+        match register {
+            1 => self.length.load(value & 0x3F),
+            2 => {
+                self.envelope.write(value);
+                self.dac_enabled = value & 0xF8 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            3 => {
+                self.shift = value >> 4;
+                self.short_mode = value & 0x08 != 0;
+                self.divisor_code = value & 0x07;
+            }
+            4 => {
+                let trigger = value & 0x80 != 0;
+                if self.length.write(value & 0x40 != 0, trigger, extra_clock) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.dac_enabled;
+                    self.timer = self.period();
+                    self.lfsr = 0x7FFF;
+                    self.envelope.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn tick(&mut self, t: u32) {
+        // Shifts of 14 and 15 stop the LFSR
+        if self.shift >= 14 {
+            return;
+        }
+        self.timer -= t as i32;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            if self.short_mode {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (bit << 6);
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    // Digital output, 0 - 15
+    pub fn output(&self) -> u8 {
+        match self.enabled {
+            true => (!self.lfsr & 1) as u8 * self.envelope.volume,
+            false => 0,
+        }
+    }
+
+    fn period(&self) -> i32 {
+        (DIVISORS[self.divisor_code as usize] << self.shift) as i32
+    }
+}
+
+#[test]
+fn test_noise_lfsr_period() {
+    // The 7-bit LFSR repeats every 127 shifts, the 15-bit one every 32767
+    for (nr43, period) in [(0x08, 127), (0x00, 32767)] {
+        let mut noise = Noise::new();
+        noise.write(2, 0xF0, false);
+        noise.write(3, nr43, false);
+        noise.write(4, 0x80, false);
+
+        let mut outputs = vec![];
+        for _ in 0..period * 2 {
+            noise.tick(8);
+            outputs.push(noise.output());
+        }
+        assert_eq!(outputs[..period], outputs[period..]);
+        assert_ne!(outputs[..period / 2], outputs[period / 2..period]);
+    }
+}
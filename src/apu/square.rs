@@ -0,0 +1,258 @@
+use super::{envelope::Envelope, length::LengthCounter};
+
+/*
+
+Square channels (NR10 - NR14, NR21 - NR24)
+
+NR10: Bit 6-4: Sweep period in 128 Hz steps (0=Disabled)
+      Bit 3: Sweep direction (1=Decrease)
+      Bit 2-0: Sweep shift
+NRx1: Bit 7-6: Duty cycle
+      Bit 5-0: Length (64-n)
+NRx2: Envelope
+NRx3: Frequency low 8 bits
+NRx4: Bit 7: Trigger
+      Bit 6: Length enable
+      Bit 2-0: Frequency high 3 bits
+
+Only channel 1 has a sweep. The duty position advances every
+(2048 - frequency) * 4 t-cycles.
+
+*/
+
+const DUTY_CYCLES: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    shadow: u16,
+    enabled: bool,
+    // Set once a frequency has been calculated in negate mode; clearing the
+    // direction afterwards switches the channel off
+    negate_used: bool,
+}
+
+impl Sweep {
+    fn write(&mut self, value: u8) -> bool {
+        self.period = (value >> 4) & 0x07;
+        let negate = value & 0x08 != 0;
+        let disable = self.negate && !negate && self.negate_used;
+        self.negate = negate;
+        self.shift = value & 0x07;
+        disable
+    }
+
+    // Returns None when the new frequency overflows 2047
+    fn calculate(&mut self) -> Option<u16> {
+        let delta = self.shadow >> self.shift;
+        let frequency = match self.negate {
+            true => {
+                self.negate_used = true;
+                self.shadow - delta
+            }
+            false => self.shadow + delta,
+        };
+        (frequency <= 2047).then_some(frequency)
+    }
+
+    fn reload(&mut self) {
+        self.timer = match self.period {
+            0 => 8,
+            period => period,
+        };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Square {
+    pub enabled: bool,
+    pub dac_enabled: bool,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    timer: i32,
+    pub length: LengthCounter,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl Square {
+    pub fn new(has_sweep: bool) -> Self {
+        Square {
+            enabled: false,
+            dac_enabled: false,
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+            sweep: has_sweep.then(Sweep::default),
+        }
+    }
+
+    // Everything but the length counter is cleared when the APU powers off
+    pub fn power_off(&mut self) {
+        let mut length = self.length.clone();
+        length.enabled = false;
+        *self = Square {
+            length,
+            ..Square::new(self.sweep.is_some())
+        };
+    }
+
+    // `register` is the offset from NR10 / NR20; `extra_clock` is passed on
+    // to the length counter
+    pub fn write(&mut self, register: u16, value: u8, extra_clock: bool) {
+        match register {
+            0 => {
+                if let Some(sweep) = &mut self.sweep {
+                    if sweep.write(value) {
+                        self.enabled = false;
+                    }
+                }
+            }
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F);
+            }
+            2 => {
+                self.envelope.write(value);
+                self.dac_enabled = value & 0xF8 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            _ => {
+                self.frequency = (self.frequency & 0xFF) | ((value as u16 & 0x07) << 8);
+                let trigger = value & 0x80 != 0;
+                if self.length.write(value & 0x40 != 0, trigger, extra_clock) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.trigger();
+                }
+            }
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.timer = self.period();
+        self.envelope.trigger();
+        if let Some(sweep) = &mut self.sweep {
+            sweep.shadow = self.frequency;
+            sweep.negate_used = false;
+            sweep.reload();
+            sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+            // The overflow check runs straight away when there is a shift
+            if sweep.shift != 0 && sweep.calculate().is_none() {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn tick(&mut self, t: u32) {
+        self.timer -= t as i32;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = &mut self.sweep else {
+            return;
+        };
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 {
+            return;
+        }
+        sweep.reload();
+        if !sweep.enabled || sweep.period == 0 {
+            return;
+        }
+        match sweep.calculate() {
+            Some(frequency) if sweep.shift != 0 => {
+                sweep.shadow = frequency;
+                self.frequency = frequency;
+                // The new frequency is checked again, but not written back
+                if sweep.calculate().is_none() {
+                    self.enabled = false;
+                }
+            }
+            Some(_) => {}
+            None => self.enabled = false,
+        }
+    }
+
+    // Digital output, 0 - 15
+    pub fn output(&self) -> u8 {
+        match self.enabled {
+            true => DUTY_CYCLES[self.duty as usize][self.duty_step as usize] * self.envelope.volume,
+            false => 0,
+        }
+    }
+
+    fn period(&self) -> i32 {
+        (2048 - self.frequency as i32) * 4
+    }
+}
+
+#[test]
+fn test_square_duty() {
+    let mut square = Square::new(false);
+    // 75% duty, full volume, frequency 2047 (4 t-cycles per step)
+    square.write(1, 0xC0, false);
+    square.write(2, 0xF0, false);
+    square.write(3, 0xFF, false);
+    square.write(4, 0x87, false);
+    assert!(square.enabled);
+
+    let mut outputs = vec![];
+    for _ in 0..8 {
+        square.tick(4);
+        outputs.push(square.output());
+    }
+    assert_eq!(outputs, vec![15, 15, 15, 15, 15, 15, 0, 0]);
+}
+
+#[test]
+fn test_sweep_overflow() {
+    let mut square = Square::new(true);
+    // Period 1, increase, shift 1
+    square.write(0, 0x11, false);
+    square.write(2, 0xF0, false);
+    square.write(3, 0x00, false);
+    square.write(4, 0x85, false);
+    assert!(square.enabled);
+
+    // 0x500 -> 0x780, whose next step (0xB40) overflows
+    square.clock_sweep();
+    assert_eq!(square.frequency, 0x780);
+    assert!(!square.enabled);
+
+    // Triggering with a frequency that overflows right away
+    square.write(3, 0xFF, false);
+    square.write(4, 0x87, false);
+    assert!(!square.enabled);
+}
@@ -0,0 +1,141 @@
+use super::length::LengthCounter;
+
+/*
+
+Wave channel (NR30 - NR34, wave RAM 0xFF30 - 0xFF3F)
+
+NR30: Bit 7: DAC power
+NR31: Length (256-n)
+NR32: Bit 6-5: Output level (0=Mute, 1=100%, 2=50%, 3=25%)
+NR33: Frequency low 8 bits
+NR34: Bit 7: Trigger
+      Bit 6: Length enable
+      Bit 2-0: Frequency high 3 bits
+
+Wave RAM holds 32 4-bit samples, upper nibble first. The position advances
+every (2048 - frequency) * 2 t-cycles.
+
+*/
+
+#[derive(Debug, Clone)]
+pub struct Wave {
+    pub enabled: bool,
+    pub dac_enabled: bool,
+    output_level: u8,
+    frequency: u16,
+    timer: i32,
+    position: u8,
+    // Last sample read from wave RAM
+    sample: u8,
+    pub length: LengthCounter,
+    pub ram: [u8; 16],
+}
+
+impl Wave {
+    pub(crate) fn new() -> Self {
+        Wave {
+            enabled: false,
+            dac_enabled: false,
+            output_level: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample: 0,
+            length: LengthCounter::new(256),
+            ram: [0; 16],
+        }
+    }
+
+    // Wave RAM and the length counter survive the APU powering off
+    pub fn power_off(&mut self) {
+        let mut length = self.length.clone();
+        length.enabled = false;
+        *self = Wave {
+            length,
+            ram: self.ram,
+            ..Wave::new()
+        };
+    }
+
+    // `register` is the offset from NR30
+    pub fn write(&mut self, register: u16, value: u8, extra_clock: bool) {
+        match register {
+            0 => {
+                self.dac_enabled = value & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(value),
+            2 => self.output_level = (value >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            _ => {
+                self.frequency = (self.frequency & 0xFF) | ((value as u16 & 0x07) << 8);
+                let trigger = value & 0x80 != 0;
+                if self.length.write(value & 0x40 != 0, trigger, extra_clock) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.dac_enabled;
+                    self.timer = self.period();
+                    self.position = 0;
+                }
+            }
+        }
+    }
+
+    pub fn tick(&mut self, t: u32) {
+        self.timer -= t as i32;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            self.position = (self.position + 1) % 32;
+            let byte = self.ram[self.position as usize / 2];
+            self.sample = match self.position % 2 {
+                0 => byte >> 4,
+                _ => byte & 0x0F,
+            };
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    // Digital output, 0 - 15
+    pub fn output(&self) -> u8 {
+        match (self.enabled, self.output_level) {
+            (false, _) | (true, 0) => 0,
+            (true, level) => self.sample >> (level - 1),
+        }
+    }
+
+    fn period(&self) -> i32 {
+        (2048 - self.frequency as i32) * 2
+    }
+}
+
+#[test]
+fn test_wave_playback() {
+    let mut wave = Wave::new();
+    wave.ram[0] = 0x1F;
+    wave.ram[1] = 0x80;
+    wave.write(0, 0x80, false);
+    // 50% volume, frequency 2047 (2 t-cycles per sample)
+    wave.write(2, 0x40, false);
+    wave.write(3, 0xFF, false);
+    wave.write(4, 0x87, false);
+
+    let mut outputs = vec![];
+    for _ in 0..3 {
+        wave.tick(2);
+        outputs.push(wave.output());
+    }
+    // Playback starts at the second sample
+    assert_eq!(outputs, vec![7, 4, 0]);
+
+    // Turning the DAC off stops the channel
+    wave.write(0, 0x00, false);
+    assert!(!wave.enabled);
+}
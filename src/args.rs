@@ -0,0 +1,145 @@
+use crate::audio::{output::DEFAULT_OUTPUT_RATE, sink::AudioBackend};
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: gb_emulator [OPTIONS] [ROM]
+
+ROMs are loaded from ./roms
+
+Options:
+    --info         Print the cartridge header and exit
+    --skip-boot    Start at 0x0100 without running the boot ROM (default when
+                   ./roms/gb_bios.bin is missing)
+    --bindings <FILE>
+                   Key and gamepad bindings, reloaded when the file changes
+                   (default ./bindings.toml)
+    --audio <device|null|wav:FILE>
+                   Where sound goes (default device when built with the audio
+                   feature, null otherwise)
+    --sample-rate <HZ>
+                   Output sample rate (default 48000)
+    --record <FILE>
+                   Record sound to a WAV file from power on
+    --record-stems Also record each channel to its own file, for recordings
+                   started from the command line or the record hotkey
+    --single-thread
+                   Run the CPU, PPU and memory on one thread with a
+                   deterministic scheduler instead of one thread each";
+
+const DEFAULT_ROM: &str = "hello-world.gb";
+const DEFAULT_BINDINGS: &str = "./bindings.toml";
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub rom_name: String,
+    // Print the cartridge header instead of running the ROM
+    pub info: bool,
+    pub skip_boot: bool,
+    pub bindings_path: String,
+    pub audio: AudioBackend,
+    pub sample_rate: u32,
+    pub record: Option<PathBuf>,
+    pub record_stems: bool,
+    pub single_thread: bool,
+}
+
+impl Args {
+    pub fn parse(args: Vec<String>) -> Result<Args, String> {
+        let mut rom_name = None;
+        let mut info = false;
+        let mut skip_boot = false;
+        let mut bindings_path = None;
+        let mut audio = AudioBackend::default();
+        let mut sample_rate = DEFAULT_OUTPUT_RATE;
+        let mut record = None;
+        let mut record_stems = false;
+        let mut single_thread = false;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--info" => info = true,
+                "--skip-boot" => skip_boot = true,
+                "--bindings" => match args.next() {
+                    Some(path) => bindings_path = Some(path),
+                    None => return Err(String::from("--bindings expects a file")),
+                },
+                "--audio" => match args.next() {
+                    Some(backend) => audio = AudioBackend::parse(&backend)?,
+                    None => return Err(String::from("--audio expects a backend")),
+                },
+                "--sample-rate" => match args.next().map(|rate| rate.parse::<u32>()) {
+                    Some(Ok(rate)) if rate > 0 => sample_rate = rate,
+                    _ => return Err(String::from("--sample-rate expects a rate in Hz")),
+                },
+                "--record" => match args.next() {
+                    Some(path) => record = Some(PathBuf::from(path)),
+                    None => return Err(String::from("--record expects a file")),
+                },
+                "--record-stems" => record_stems = true,
+                "--single-thread" => single_thread = true,
+                flag if flag.starts_with("--") => return Err(format!("Unknown option {flag}")),
+                _ => match rom_name {
+                    None => rom_name = Some(arg),
+                    Some(_) => return Err(format!("Unexpected argument {arg}")),
+                },
+            }
+        }
+
+        Ok(Args {
+            rom_name: rom_name.unwrap_or_else(|| String::from(DEFAULT_ROM)),
+            info,
+            skip_boot,
+            bindings_path: bindings_path.unwrap_or_else(|| String::from(DEFAULT_BINDINGS)),
+            audio,
+            sample_rate,
+            record,
+            record_stems,
+            single_thread,
+        })
+    }
+}
+
+#[test]
+fn test_parse_args() {
+    let args = Args::parse(vec![String::from("--info"), String::from("tetris.gb")]).unwrap();
+    assert_eq!(args.rom_name, "tetris.gb");
+    assert!(args.info);
+
+    let args = Args::parse(vec![]).unwrap();
+    assert_eq!(args.rom_name, DEFAULT_ROM);
+    assert!(!args.info);
+    assert!(!args.skip_boot);
+
+    let args = Args::parse(vec![String::from("--skip-boot")]).unwrap();
+    assert!(args.skip_boot);
+    assert!(!args.single_thread);
+
+    let args = Args::parse(vec![String::from("--single-thread")]).unwrap();
+    assert!(args.single_thread);
+
+    let args = Args::parse(vec![String::from("--bindings"), String::from("pad.toml")]).unwrap();
+    assert_eq!(args.bindings_path, "pad.toml");
+    assert_eq!(args.rom_name, DEFAULT_ROM);
+    assert!(Args::parse(vec![String::from("--bindings")]).is_err());
+
+    let args = Args::parse(
+        ["--audio", "null", "--sample-rate", "44100"]
+            .map(String::from)
+            .to_vec(),
+    )
+    .unwrap();
+    assert_eq!(args.audio, AudioBackend::Null);
+    assert_eq!(args.sample_rate, 44100);
+    assert!(Args::parse(["--sample-rate", "fast"].map(String::from).to_vec()).is_err());
+
+    let args = Args::parse(
+        ["--record-stems", "--record", "song.wav"]
+            .map(String::from)
+            .to_vec(),
+    )
+    .unwrap();
+    assert_eq!(args.record, Some(PathBuf::from("song.wav")));
+    assert!(args.record_stems);
+
+    assert!(Args::parse(vec![String::from("--nope")]).is_err());
+}
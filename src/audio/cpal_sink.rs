@@ -0,0 +1,88 @@
+use super::sink::AudioSink;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/*
+
+cpal output
+
+Samples are queued for the device callback, which plays silence when the
+queue runs dry. `write` blocks while the queue holds more than twice the
+target latency.
+
+*/
+
+const TARGET_LATENCY: Duration = Duration::from_millis(50);
+
+pub struct CpalSink {
+    // Never read, but the device stops when it is dropped
+    _stream: cpal::Stream,
+    queue: Arc<Mutex<VecDeque<[f32; 2]>>>,
+    sample_rate: u32,
+    // Queue length at a buffer level of 1.0
+    capacity: usize,
+}
+
+impl CpalSink {
+    pub fn new(sample_rate: u32) -> Result<CpalSink, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| String::from("no audio output device"))?;
+        let config = cpal::StreamConfig {
+            channels: 2,
+            sample_rate: cpal::SampleRate(sample_rate),
+            buffer_size: cpal::BufferSize::Default,
+        };
+
+        let queue = Arc::new(Mutex::new(VecDeque::<[f32; 2]>::new()));
+        let callback_queue = queue.clone();
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                    let mut queue = callback_queue.lock().unwrap();
+                    for frame in data.chunks_exact_mut(2) {
+                        frame.copy_from_slice(&queue.pop_front().unwrap_or([0.0; 2]));
+                    }
+                },
+                |err| println!("Warning: audio stream error: {err:}"),
+                None,
+            )
+            .map_err(|err| err.to_string())?;
+        stream.play().map_err(|err| err.to_string())?;
+
+        let capacity = (sample_rate as f64 * TARGET_LATENCY.as_secs_f64() * 2.0) as usize;
+        Ok(CpalSink {
+            _stream: stream,
+            queue,
+            sample_rate,
+            capacity,
+        })
+    }
+
+    fn queued(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+}
+
+impl AudioSink for CpalSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn write(&mut self, samples: &[[f32; 2]]) {
+        while self.queued() > self.capacity {
+            thread::sleep(Duration::from_millis(1));
+        }
+        self.queue.lock().unwrap().extend(samples);
+    }
+
+    fn buffer_level(&self) -> Option<f64> {
+        Some((self.queued() as f64 / self.capacity as f64).min(1.0))
+    }
+}
@@ -0,0 +1,7 @@
+#[cfg(feature = "audio")]
+pub mod cpal_sink;
+pub mod output;
+pub mod recorder;
+pub mod resampler;
+pub mod sink;
+pub mod wav;
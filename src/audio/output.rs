@@ -0,0 +1,84 @@
+use super::{
+    resampler::Resampler,
+    sink::{AudioBackend, AudioSink, NullSink, WavSink},
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::Receiver,
+    Arc,
+};
+
+/*
+
+Audio output
+
+Batches of APU samples arrive from the memory thread over a bounded channel
+and are resampled to the sink's rate. For real-time sinks the output rate is
+nudged by up to MAX_RATE_DELTA to keep the sink's queue half full, so small
+differences between the emulated and the device clock never run it dry or
+overflow it. A full sink blocks, which fills the channel and in turn blocks
+the memory thread; the audio device sets the pace of emulation.
+
+*/
+
+pub const DEFAULT_OUTPUT_RATE: u32 = 48000;
+
+const MAX_RATE_DELTA: f64 = 0.005;
+
+// Falls back to the null sink when the backend can't be opened
+pub fn open_sink(backend: &AudioBackend, sample_rate: u32) -> Box<dyn AudioSink> {
+    let sink: Result<Box<dyn AudioSink>, String> = match backend {
+        AudioBackend::Null => Ok(Box::new(NullSink::new(sample_rate))),
+        AudioBackend::Wav(path) => {
+            WavSink::create(path, sample_rate).map(|sink| Box::new(sink) as Box<dyn AudioSink>)
+        }
+        #[cfg(feature = "audio")]
+        AudioBackend::Device => super::cpal_sink::CpalSink::new(sample_rate)
+            .map(|sink| Box::new(sink) as Box<dyn AudioSink>),
+        #[cfg(not(feature = "audio"))]
+        AudioBackend::Device => Err(String::from("built without the audio feature")),
+    };
+    match sink {
+        Ok(sink) => sink,
+        Err(err) => {
+            println!("Warning: no sound ({err})");
+            Box::new(NullSink::new(sample_rate))
+        }
+    }
+}
+
+// Output rate that moves the sink's queue towards half full
+pub fn adjusted_rate(sample_rate: u32, buffer_level: f64) -> f64 {
+    sample_rate as f64 * (1.0 + MAX_RATE_DELTA * (1.0 - 2.0 * buffer_level))
+}
+
+// Runs until the memory thread hangs up. While fast-forwarding samples are
+// dropped, so the sink doesn't hold emulation back
+pub fn run(
+    receiver: Receiver<Vec<[f32; 2]>>,
+    mut sink: Box<dyn AudioSink>,
+    input_rate: u32,
+    fast_forward: Arc<AtomicBool>,
+) {
+    let mut resampler = Resampler::new(input_rate, sink.sample_rate());
+    let mut output = vec![];
+    while let Ok(samples) = receiver.recv() {
+        if fast_forward.load(Ordering::Relaxed) {
+            continue;
+        }
+        if let Some(level) = sink.buffer_level() {
+            resampler.set_output_rate(adjusted_rate(sink.sample_rate(), level));
+        }
+        output.clear();
+        resampler.process(&samples, &mut output);
+        sink.write(&output);
+    }
+}
+
+#[test]
+fn test_adjusted_rate() {
+    assert!((adjusted_rate(48000, 0.5) - 48000.0).abs() < 1e-6);
+    // An emptying queue gets more samples, a filling one fewer
+    assert!((adjusted_rate(48000, 0.0) - 48240.0).abs() < 1e-6);
+    assert!((adjusted_rate(48000, 1.0) - 47760.0).abs() < 1e-6);
+}
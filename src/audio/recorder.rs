@@ -0,0 +1,104 @@
+use super::{resampler::Resampler, wav::WavWriter};
+use std::path::{Path, PathBuf};
+
+/*
+
+Audio recorder
+
+Writes the APU output, and optionally each channel on its own, to WAV files.
+It is fed from the memory thread with the samples the APU produced, so a
+recording covers exactly the emulated cycles between starting and stopping
+it, however fast emulation ran; the same ROM and inputs give the same file.
+
+Stems are mono and named after the main file, e.g. music-ch1.wav.
+
+*/
+
+#[derive(Debug)]
+pub struct Recorder {
+    mix: WavWriter,
+    resampler: Resampler,
+    stems: Vec<(WavWriter, Resampler)>,
+    output: Vec<[f32; 2]>,
+}
+
+impl Recorder {
+    pub fn create(
+        path: &Path,
+        stems: bool,
+        input_rate: u32,
+        output_rate: u32,
+    ) -> Result<Recorder, String> {
+        let create = |path: &Path, channels| {
+            WavWriter::create(path, output_rate, channels)
+                .map_err(|err| format!("{}: {err:}", path.display()))
+        };
+        let mix = create(path, 2)?;
+        let stems = match stems {
+            true => (1..=4)
+                .map(|channel| {
+                    let writer = create(&Recorder::stem_path(path, channel), 1)?;
+                    Ok((writer, Resampler::new(input_rate, output_rate)))
+                })
+                .collect::<Result<Vec<_>, String>>()?,
+            false => vec![],
+        };
+
+        Ok(Recorder {
+            mix,
+            resampler: Resampler::new(input_rate, output_rate),
+            stems,
+            output: vec![],
+        })
+    }
+
+    pub fn stem_path(path: &Path, channel: u8) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{stem}-ch{channel}.wav"))
+    }
+
+    // `stems` is ignored unless the recorder was created with stems
+    pub fn write(&mut self, samples: &[[f32; 2]], stems: &[[f32; 4]]) -> Result<(), String> {
+        self.output.clear();
+        self.resampler.process(samples, &mut self.output);
+        let data: Vec<f32> = self.output.iter().flatten().copied().collect();
+        self.mix.write(&data).map_err(|err| err.to_string())?;
+
+        for (channel, (writer, resampler)) in self.stems.iter_mut().enumerate() {
+            let input: Vec<[f32; 2]> = stems.iter().map(|x| [x[channel]; 2]).collect();
+            self.output.clear();
+            resampler.process(&input, &mut self.output);
+            let data: Vec<f32> = self.output.iter().map(|x| x[0]).collect();
+            writer.write(&data).map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_recorder_stems() {
+    let dir = std::env::temp_dir();
+    let path = dir.join(format!("gb_record_test_{}.wav", std::process::id()));
+    let mut recorder = Recorder::create(&path, true, 1_048_576, 48000).unwrap();
+    recorder
+        .write(
+            &vec![[0.25, -0.25]; 0x10000],
+            &vec![[0.1, 0.2, 0.3, 0.4]; 0x10000],
+        )
+        .unwrap();
+    drop(recorder);
+
+    let mix = std::fs::read(&path).unwrap();
+    let stem = std::fs::read(Recorder::stem_path(&path, 3)).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    for channel in 1..=4 {
+        std::fs::remove_file(Recorder::stem_path(&path, channel)).unwrap();
+    }
+
+    // Mono stems are half the size of the stereo mix
+    assert!(mix.len() > 44 + 2900 * 4);
+    assert_eq!(stem.len() - 44, (mix.len() - 44) / 2);
+    // Well past the filter's start-up, the stem holds the channel's level
+    let sample = i16::from_le_bytes([stem[44 + 2000], stem[45 + 2000]]);
+    assert!((sample as f32 / i16::MAX as f32 - 0.3).abs() < 0.01);
+}
@@ -0,0 +1,161 @@
+use std::f64::consts::PI;
+
+/*
+
+Band-limited resampler
+
+The APU runs at ~1 MHz, far above any output device. Input is first averaged
+over blocks of DECIMATION samples; the box filter's nulls fall on multiples
+of the reduced rate, which is where the content that would alias into the
+audible band sits. The reduced stream is then interpolated at the output rate
+through a windowed-sinc low-pass, looked up from a table of PHASES
+fractional offsets.
+
+The output rate can be nudged while running for dynamic rate control; the
+filter is designed for the nominal rate and tolerates small changes.
+
+*/
+
+const DECIMATION: usize = 8;
+// Filter taps on each side of the interpolated point
+const HALF_TAPS: usize = 48;
+const PHASES: usize = 256;
+// Passband edge as a fraction of the output Nyquist frequency
+const CUTOFF: f64 = 0.9;
+
+#[derive(Debug)]
+pub struct Resampler {
+    // Input samples per second after decimation
+    decimated_rate: f64,
+    // Decimated samples per output sample
+    step: f64,
+    block_sum: [f32; 2],
+    block_len: usize,
+    history: Vec<[f32; 2]>,
+    // Position of the next output sample in `history`
+    position: f64,
+    // PHASES + 1 rows of HALF_TAPS * 2 weights
+    kernel: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        let decimated_rate = input_rate as f64 / DECIMATION as f64;
+        // Cutoff in cycles per decimated sample
+        let cutoff = (output_rate as f64 / 2.0 * CUTOFF / decimated_rate).min(0.5);
+        let taps = HALF_TAPS * 2;
+        let mut kernel = Vec::with_capacity((PHASES + 1) * taps);
+        for phase in 0..=PHASES {
+            let frac = phase as f64 / PHASES as f64;
+            for k in 0..taps {
+                // Distance from the interpolated point to the tap
+                let x = frac + HALF_TAPS as f64 - 1.0 - k as f64;
+                kernel.push(windowed_sinc(x, cutoff) as f32);
+            }
+        }
+
+        Resampler {
+            decimated_rate,
+            step: decimated_rate / output_rate as f64,
+            block_sum: [0.0; 2],
+            block_len: 0,
+            history: vec![[0.0; 2]; taps],
+            position: HALF_TAPS as f64,
+            kernel,
+        }
+    }
+
+    pub fn set_output_rate(&mut self, output_rate: f64) {
+        self.step = self.decimated_rate / output_rate;
+    }
+
+    pub fn process(&mut self, input: &[[f32; 2]], output: &mut Vec<[f32; 2]>) {
+        for sample in input {
+            self.block_sum[0] += sample[0];
+            self.block_sum[1] += sample[1];
+            self.block_len += 1;
+            if self.block_len == DECIMATION {
+                let scale = 1.0 / DECIMATION as f32;
+                self.history
+                    .push([self.block_sum[0] * scale, self.block_sum[1] * scale]);
+                self.block_sum = [0.0; 2];
+                self.block_len = 0;
+            }
+        }
+
+        let taps = HALF_TAPS * 2;
+        while self.position as usize + HALF_TAPS < self.history.len() {
+            let base = self.position as usize;
+            let frac = self.position - base as f64;
+            let phase = (frac * PHASES as f64).round() as usize;
+            let weights = &self.kernel[phase * taps..(phase + 1) * taps];
+            let window = &self.history[base + 1 - HALF_TAPS..base + 1 + HALF_TAPS];
+
+            let mut sample = [0.0; 2];
+            for (weight, input) in weights.iter().zip(window) {
+                sample[0] += weight * input[0];
+                sample[1] += weight * input[1];
+            }
+            output.push(sample);
+            self.position += self.step;
+        }
+
+        // Drop the samples no future output can reach
+        let consumed = (self.position as usize).saturating_sub(HALF_TAPS);
+        self.history.drain(..consumed);
+        self.position -= consumed as f64;
+    }
+}
+
+// Low-pass impulse response with a Blackman window spanning HALF_TAPS
+fn windowed_sinc(x: f64, cutoff: f64) -> f64 {
+    if x.abs() >= HALF_TAPS as f64 {
+        return 0.0;
+    }
+    let sinc = match x == 0.0 {
+        true => 1.0,
+        false => (2.0 * PI * cutoff * x).sin() / (2.0 * PI * cutoff * x),
+    };
+    let w = PI * x / HALF_TAPS as f64;
+    let window = 0.42 + 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+    2.0 * cutoff * sinc * window
+}
+
+#[cfg(test)]
+fn resample_tone(frequency: f64) -> Vec<[f32; 2]> {
+    let input_rate = 1_048_576;
+    let mut resampler = Resampler::new(input_rate, 48000);
+    let input: Vec<[f32; 2]> = (0..input_rate / 4)
+        .map(|i| {
+            let x = (2.0 * PI * frequency * i as f64 / input_rate as f64).sin() as f32;
+            [x, x]
+        })
+        .collect();
+    let mut output = vec![];
+    // Fed in uneven chunks, like batches from the APU
+    for chunk in input.chunks(3000) {
+        resampler.process(chunk, &mut output);
+    }
+    output
+}
+
+#[cfg(test)]
+fn peak(samples: &[[f32; 2]]) -> f32 {
+    // Skip the filter's start-up
+    samples[100..].iter().fold(0.0, |x, s| x.max(s[0].abs()))
+}
+
+#[test]
+fn test_resampler_rate() {
+    let output = resample_tone(1000.0);
+    // A quarter second at 48 kHz
+    assert!((11990..=12010).contains(&output.len()));
+    assert!((peak(&output) - 1.0).abs() < 0.02);
+}
+
+#[test]
+fn test_resampler_band_limited() {
+    // 30 kHz would alias down to 18 kHz without the low-pass
+    let output = resample_tone(30000.0);
+    assert!(peak(&output) < 0.01);
+}
@@ -0,0 +1,124 @@
+use super::wav::WavWriter;
+use std::path::{Path, PathBuf};
+
+/*
+
+Audio sinks
+
+Sinks take stereo samples at their own sample rate. Sinks that play in real
+time report how full their queue is, which drives the dynamic rate control
+in `output`, and block in `write` while the queue is full, which keeps
+emulation running at real speed.
+
+*/
+
+pub trait AudioSink {
+    fn sample_rate(&self) -> u32;
+
+    // Samples are [left, right] in -1.0 - 1.0
+    fn write(&mut self, samples: &[[f32; 2]]);
+
+    // Queue fill level, 0.0 - 1.0 with 0.5 as the target; None for sinks
+    // that don't play in real time
+    fn buffer_level(&self) -> Option<f64> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioBackend {
+    // The default output device, through cpal
+    Device,
+    Null,
+    Wav(PathBuf),
+}
+
+impl AudioBackend {
+    pub fn parse(name: &str) -> Result<AudioBackend, String> {
+        match name {
+            "device" => Ok(AudioBackend::Device),
+            "null" => Ok(AudioBackend::Null),
+            _ => match name.strip_prefix("wav:") {
+                Some(path) if !path.is_empty() => Ok(AudioBackend::Wav(PathBuf::from(path))),
+                _ => Err(format!("Unknown audio backend {name}")),
+            },
+        }
+    }
+}
+
+impl Default for AudioBackend {
+    fn default() -> Self {
+        match cfg!(feature = "audio") {
+            true => AudioBackend::Device,
+            false => AudioBackend::Null,
+        }
+    }
+}
+
+// Discards everything, for running without sound
+#[derive(Debug)]
+pub struct NullSink {
+    sample_rate: u32,
+}
+
+impl NullSink {
+    pub fn new(sample_rate: u32) -> Self {
+        NullSink { sample_rate }
+    }
+}
+
+impl AudioSink for NullSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn write(&mut self, _samples: &[[f32; 2]]) {}
+}
+
+// Writes everything to a 16-bit stereo WAV file; usable without an audio
+// device, e.g. on CI
+#[derive(Debug)]
+pub struct WavSink {
+    writer: Option<WavWriter>,
+    sample_rate: u32,
+}
+
+impl WavSink {
+    pub fn create(path: &Path, sample_rate: u32) -> Result<WavSink, String> {
+        let writer = WavWriter::create(path, sample_rate, 2)
+            .map_err(|err| format!("{}: {err:}", path.display()))?;
+        Ok(WavSink {
+            writer: Some(writer),
+            sample_rate,
+        })
+    }
+}
+
+impl AudioSink for WavSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn write(&mut self, samples: &[[f32; 2]]) {
+        let Some(writer) = &mut self.writer else {
+            return;
+        };
+        let data: Vec<f32> = samples.iter().flatten().copied().collect();
+        if let Err(err) = writer.write(&data) {
+            // Stop after the first failure rather than warning on every batch
+            println!("Warning: could not write audio: {err:}");
+            self.writer = None;
+        }
+    }
+}
+
+#[test]
+fn test_parse_audio_backend() {
+    assert_eq!(AudioBackend::parse("null"), Ok(AudioBackend::Null));
+    assert_eq!(
+        AudioBackend::parse("wav:out.wav"),
+        Ok(AudioBackend::Wav(PathBuf::from("out.wav")))
+    );
+    assert!(AudioBackend::parse("wav:").is_err());
+    assert!(AudioBackend::parse("speakers").is_err());
+}
@@ -0,0 +1,92 @@
+use std::{
+    fs::File,
+    io::{self, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/*
+
+WAV file (16-bit PCM)
+
+0x00: "RIFF", file size - 8, "WAVE"
+0x0C: "fmt ", 16, format (1=PCM), channels, sample rate, byte rate,
+      block align, bits per sample
+0x24: "data", data size, samples
+
+The sizes are rewritten after every write, so the file stays playable if the
+emulator exits without closing it.
+
+*/
+
+const HEADER_LEN: u32 = 44;
+
+#[derive(Debug)]
+pub struct WavWriter {
+    file: File,
+    channels: u16,
+    data_len: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32, channels: u16) -> io::Result<WavWriter> {
+        let mut file = File::create(path)?;
+        let block_align = channels * 2;
+        let mut header = Vec::with_capacity(HEADER_LEN as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(HEADER_LEN - 8).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        file.write_all(&header)?;
+
+        Ok(WavWriter {
+            file,
+            channels,
+            data_len: 0,
+        })
+    }
+
+    // `samples` holds `channels` interleaved values per frame, -1.0 - 1.0
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        debug_assert_eq!(samples.len() % self.channels as usize, 0);
+        let data: Vec<u8> = samples
+            .iter()
+            .flat_map(|x| ((x.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+            .collect();
+        self.file.write_all(&data)?;
+        self.data_len += data.len() as u32;
+
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file
+            .write_all(&(HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(HEADER_LEN as u64 - 4))?;
+        self.file.write_all(&self.data_len.to_le_bytes())?;
+        self.file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_wav_header() {
+    let path = std::env::temp_dir().join(format!("gb_wav_test_{}.wav", std::process::id()));
+    let mut wav = WavWriter::create(&path, 48000, 2).unwrap();
+    wav.write(&[0.0, 1.0, -1.0, 0.5]).unwrap();
+    drop(wav);
+
+    let data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(data.len(), 44 + 8);
+    assert_eq!(&data[0..4], b"RIFF");
+    assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()), 44);
+    assert_eq!(u32::from_le_bytes(data[24..28].try_into().unwrap()), 48000);
+    assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 8);
+    assert_eq!(i16::from_le_bytes([data[46], data[47]]), i16::MAX);
+    assert_eq!(i16::from_le_bytes([data[48], data[49]]), -i16::MAX);
+}
@@ -0,0 +1,511 @@
+use crate::joypad::Button;
+use serde::{de::IntoDeserializer, Deserialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::Path,
+};
+use winit::event::VirtualKeyCode;
+
+/*
+
+Bindings file (TOML)
+
+Each section maps an action to the list of inputs that trigger it. Actions
+left out keep their default bindings; an empty list unbinds the action.
+
+[keyboard]
+a = ["Z"]
+start = ["Return", "Space"]
+fast_forward = ["Tab"]
+
+[gamepad]
+a = ["East"]
+left = ["DPadLeft", "LeftStickX-"]
+
+Actions: right, left, up, down, a, b, select, start, pause, fast_forward,
+         screenshot, fullscreen, record
+Keys: winit VirtualKeyCode names (Z, Key1, Return, Back, F11, ...)
+Gamepad buttons: South, East, North, West, LeftTrigger, LeftTrigger2,
+                 RightTrigger, RightTrigger2, Select, Start, Mode, LeftThumb,
+                 RightThumb, DPadUp, DPadDown, DPadLeft, DPadRight
+Gamepad axes: LeftStickX, LeftStickY, RightStickX, RightStickY, followed by
+              + or - for the direction
+
+*/
+
+// How far an axis has to be pushed to count as a button press
+pub const AXIS_THRESHOLD: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hotkey {
+    Pause,
+    FastForward,
+    Screenshot,
+    Fullscreen,
+    // Starts or stops recording audio to a WAV file
+    Record,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Joypad(Button),
+    Hotkey(Hotkey),
+}
+
+impl Action {
+    fn from_name(name: &str) -> Option<Action> {
+        Some(match name {
+            "right" => Action::Joypad(Button::Right),
+            "left" => Action::Joypad(Button::Left),
+            "up" => Action::Joypad(Button::Up),
+            "down" => Action::Joypad(Button::Down),
+            "a" => Action::Joypad(Button::A),
+            "b" => Action::Joypad(Button::B),
+            "select" => Action::Joypad(Button::Select),
+            "start" => Action::Joypad(Button::Start),
+            "pause" => Action::Hotkey(Hotkey::Pause),
+            "fast_forward" => Action::Hotkey(Hotkey::FastForward),
+            "screenshot" => Action::Hotkey(Hotkey::Screenshot),
+            "fullscreen" => Action::Hotkey(Hotkey::Fullscreen),
+            "record" => Action::Hotkey(Hotkey::Record),
+            _ => return None,
+        })
+    }
+}
+
+// Named after their gilrs counterparts, so the bindings file can be parsed
+// without gamepad support compiled in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    Mode,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+impl GamepadButton {
+    fn from_name(name: &str) -> Option<GamepadButton> {
+        Some(match name {
+            "South" => GamepadButton::South,
+            "East" => GamepadButton::East,
+            "North" => GamepadButton::North,
+            "West" => GamepadButton::West,
+            "LeftTrigger" => GamepadButton::LeftTrigger,
+            "LeftTrigger2" => GamepadButton::LeftTrigger2,
+            "RightTrigger" => GamepadButton::RightTrigger,
+            "RightTrigger2" => GamepadButton::RightTrigger2,
+            "Select" => GamepadButton::Select,
+            "Start" => GamepadButton::Start,
+            "Mode" => GamepadButton::Mode,
+            "LeftThumb" => GamepadButton::LeftThumb,
+            "RightThumb" => GamepadButton::RightThumb,
+            "DPadUp" => GamepadButton::DPadUp,
+            "DPadDown" => GamepadButton::DPadDown,
+            "DPadLeft" => GamepadButton::DPadLeft,
+            "DPadRight" => GamepadButton::DPadRight,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+}
+
+impl GamepadAxis {
+    fn from_name(name: &str) -> Option<GamepadAxis> {
+        Some(match name {
+            "LeftStickX" => GamepadAxis::LeftStickX,
+            "LeftStickY" => GamepadAxis::LeftStickY,
+            "RightStickX" => GamepadAxis::RightStickX,
+            "RightStickY" => GamepadAxis::RightStickY,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadInput {
+    Button(GamepadButton),
+    // Axis pushed in the positive (true) or negative direction
+    Axis(GamepadAxis, bool),
+}
+
+impl GamepadInput {
+    fn from_name(name: &str) -> Option<GamepadInput> {
+        if let Some(button) = GamepadButton::from_name(name) {
+            return Some(GamepadInput::Button(button));
+        }
+        let (axis, positive) = match name.strip_suffix('+') {
+            Some(axis) => (axis, true),
+            None => (name.strip_suffix('-')?, false),
+        };
+        Some(GamepadInput::Axis(GamepadAxis::from_name(axis)?, positive))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BindingsFile {
+    #[serde(default)]
+    keyboard: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    gamepad: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bindings {
+    keys: HashMap<VirtualKeyCode, Action>,
+    gamepad: HashMap<GamepadInput, Action>,
+}
+
+const DEFAULT_KEYS: [(VirtualKeyCode, Action); 13] = [
+    (VirtualKeyCode::Right, Action::Joypad(Button::Right)),
+    (VirtualKeyCode::Left, Action::Joypad(Button::Left)),
+    (VirtualKeyCode::Up, Action::Joypad(Button::Up)),
+    (VirtualKeyCode::Down, Action::Joypad(Button::Down)),
+    (VirtualKeyCode::Z, Action::Joypad(Button::A)),
+    (VirtualKeyCode::X, Action::Joypad(Button::B)),
+    (VirtualKeyCode::Back, Action::Joypad(Button::Select)),
+    (VirtualKeyCode::Return, Action::Joypad(Button::Start)),
+    (VirtualKeyCode::P, Action::Hotkey(Hotkey::Pause)),
+    (VirtualKeyCode::Tab, Action::Hotkey(Hotkey::FastForward)),
+    (VirtualKeyCode::F12, Action::Hotkey(Hotkey::Screenshot)),
+    (VirtualKeyCode::F11, Action::Hotkey(Hotkey::Fullscreen)),
+    (VirtualKeyCode::F9, Action::Hotkey(Hotkey::Record)),
+];
+
+const DEFAULT_GAMEPAD: [(GamepadInput, Action); 12] = [
+    (
+        GamepadInput::Button(GamepadButton::DPadRight),
+        Action::Joypad(Button::Right),
+    ),
+    (
+        GamepadInput::Button(GamepadButton::DPadLeft),
+        Action::Joypad(Button::Left),
+    ),
+    (
+        GamepadInput::Button(GamepadButton::DPadUp),
+        Action::Joypad(Button::Up),
+    ),
+    (
+        GamepadInput::Button(GamepadButton::DPadDown),
+        Action::Joypad(Button::Down),
+    ),
+    (
+        GamepadInput::Axis(GamepadAxis::LeftStickX, true),
+        Action::Joypad(Button::Right),
+    ),
+    (
+        GamepadInput::Axis(GamepadAxis::LeftStickX, false),
+        Action::Joypad(Button::Left),
+    ),
+    (
+        GamepadInput::Axis(GamepadAxis::LeftStickY, true),
+        Action::Joypad(Button::Up),
+    ),
+    (
+        GamepadInput::Axis(GamepadAxis::LeftStickY, false),
+        Action::Joypad(Button::Down),
+    ),
+    // Nintendo layout: A on the right, B on the bottom
+    (
+        GamepadInput::Button(GamepadButton::East),
+        Action::Joypad(Button::A),
+    ),
+    (
+        GamepadInput::Button(GamepadButton::South),
+        Action::Joypad(Button::B),
+    ),
+    (
+        GamepadInput::Button(GamepadButton::Select),
+        Action::Joypad(Button::Select),
+    ),
+    (
+        GamepadInput::Button(GamepadButton::Start),
+        Action::Joypad(Button::Start),
+    ),
+];
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings {
+            keys: HashMap::from(DEFAULT_KEYS),
+            gamepad: HashMap::from(DEFAULT_GAMEPAD),
+        }
+    }
+}
+
+impl Bindings {
+    // A missing file means the defaults; anything else wrong with it is
+    // returned as a list of errors, one per problem found
+    pub fn load(path: &Path) -> Result<Bindings, Vec<String>> {
+        match fs::read_to_string(path) {
+            Ok(text) => Bindings::from_toml(&text),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Bindings::default()),
+            Err(err) => Err(vec![format!("{}: {err:}", path.display())]),
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<Bindings, Vec<String>> {
+        let file: BindingsFile = toml::from_str(text).map_err(|err| vec![err.to_string()])?;
+        let mut errors = vec![];
+        let mut bindings = Bindings::default();
+
+        bindings.keys = Bindings::apply_section(
+            "keyboard",
+            &file.keyboard,
+            &bindings.keys,
+            parse_key,
+            &mut errors,
+        );
+        bindings.gamepad = Bindings::apply_section(
+            "gamepad",
+            &file.gamepad,
+            &bindings.gamepad,
+            GamepadInput::from_name,
+            &mut errors,
+        );
+
+        match errors.is_empty() {
+            true => Ok(bindings),
+            false => Err(errors),
+        }
+    }
+
+    pub fn key_action(&self, key: VirtualKeyCode) -> Option<Action> {
+        self.keys.get(&key).copied()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = (VirtualKeyCode, Action)> + '_ {
+        self.keys.iter().map(|(key, action)| (*key, *action))
+    }
+
+    pub fn gamepad_action(&self, input: GamepadInput) -> Option<Action> {
+        self.gamepad.get(&input).copied()
+    }
+
+    // Every action listed in the section replaces its default inputs
+    fn apply_section<T: Copy + Eq + std::hash::Hash>(
+        section: &str,
+        entries: &BTreeMap<String, Vec<String>>,
+        defaults: &HashMap<T, Action>,
+        parse: fn(&str) -> Option<T>,
+        errors: &mut Vec<String>,
+    ) -> HashMap<T, Action> {
+        let mut actions = BTreeMap::new();
+        for (name, inputs) in entries {
+            match Action::from_name(name) {
+                Some(action) => {
+                    actions.insert(name, (action, inputs));
+                }
+                None => errors.push(format!("[{section}] unknown action \"{name}\"")),
+            }
+        }
+
+        let mut map: HashMap<T, Action> = defaults
+            .iter()
+            .filter(|(_, action)| !actions.values().any(|(x, _)| x == *action))
+            .map(|(input, action)| (*input, *action))
+            .collect();
+        // Names of the inputs bound so far by the file, for duplicate reports
+        let mut bound_by: HashMap<T, &str> = HashMap::new();
+        for (name, (action, inputs)) in actions {
+            for input_name in inputs {
+                let Some(input) = parse(input_name) else {
+                    errors.push(format!(
+                        "[{section}] {name}: unknown input \"{input_name}\""
+                    ));
+                    continue;
+                };
+                if let Some(other) = bound_by.get(&input) {
+                    errors.push(format!(
+                        "[{section}] \"{input_name}\" is bound to both {other} and {name}"
+                    ));
+                    continue;
+                }
+                bound_by.insert(input, name);
+                map.insert(input, action);
+            }
+        }
+        map
+    }
+}
+
+// Turns analog axis positions into press and release edges, so a stick can
+// stand in for the d-pad
+#[derive(Debug, Default)]
+pub struct AxisState {
+    held: HashSet<(GamepadAxis, bool)>,
+}
+
+impl AxisState {
+    pub fn update(&mut self, axis: GamepadAxis, value: f32) -> Vec<(GamepadInput, bool)> {
+        let mut edges = vec![];
+        for (positive, pushed) in [
+            (true, value > AXIS_THRESHOLD),
+            (false, value < -AXIS_THRESHOLD),
+        ] {
+            let was_pushed = match pushed {
+                true => !self.held.insert((axis, positive)),
+                false => self.held.remove(&(axis, positive)),
+            };
+            if pushed != was_pushed {
+                edges.push((GamepadInput::Axis(axis, positive), pushed));
+            }
+        }
+        edges
+    }
+}
+
+fn parse_key(name: &str) -> Option<VirtualKeyCode> {
+    let deserializer: serde::de::value::StrDeserializer<serde::de::value::Error> =
+        name.into_deserializer();
+    VirtualKeyCode::deserialize(deserializer).ok()
+}
+
+#[cfg(feature = "gamepad")]
+impl GamepadButton {
+    pub fn from_gilrs(button: gilrs::Button) -> Option<GamepadButton> {
+        use gilrs::Button as B;
+        Some(match button {
+            B::South => GamepadButton::South,
+            B::East => GamepadButton::East,
+            B::North => GamepadButton::North,
+            B::West => GamepadButton::West,
+            B::LeftTrigger => GamepadButton::LeftTrigger,
+            B::LeftTrigger2 => GamepadButton::LeftTrigger2,
+            B::RightTrigger => GamepadButton::RightTrigger,
+            B::RightTrigger2 => GamepadButton::RightTrigger2,
+            B::Select => GamepadButton::Select,
+            B::Start => GamepadButton::Start,
+            B::Mode => GamepadButton::Mode,
+            B::LeftThumb => GamepadButton::LeftThumb,
+            B::RightThumb => GamepadButton::RightThumb,
+            B::DPadUp => GamepadButton::DPadUp,
+            B::DPadDown => GamepadButton::DPadDown,
+            B::DPadLeft => GamepadButton::DPadLeft,
+            B::DPadRight => GamepadButton::DPadRight,
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(feature = "gamepad")]
+impl GamepadAxis {
+    pub fn from_gilrs(axis: gilrs::Axis) -> Option<GamepadAxis> {
+        Some(match axis {
+            gilrs::Axis::LeftStickX => GamepadAxis::LeftStickX,
+            gilrs::Axis::LeftStickY => GamepadAxis::LeftStickY,
+            gilrs::Axis::RightStickX => GamepadAxis::RightStickX,
+            gilrs::Axis::RightStickY => GamepadAxis::RightStickY,
+            _ => return None,
+        })
+    }
+}
+
+#[test]
+fn test_bindings_override_defaults() {
+    let bindings = Bindings::from_toml(
+        r#"
+        [keyboard]
+        a = ["Space", "K"]
+        screenshot = []
+
+        [gamepad]
+        a = ["South", "RightStickX+"]
+        "#,
+    )
+    .unwrap();
+
+    let a = Some(Action::Joypad(Button::A));
+    assert_eq!(bindings.key_action(VirtualKeyCode::Space), a);
+    assert_eq!(bindings.key_action(VirtualKeyCode::K), a);
+    // The default key for A is replaced, the others are kept
+    assert_eq!(bindings.key_action(VirtualKeyCode::Z), None);
+    assert_eq!(
+        bindings.key_action(VirtualKeyCode::X),
+        Some(Action::Joypad(Button::B))
+    );
+    assert_eq!(bindings.key_action(VirtualKeyCode::F12), None);
+
+    // South was B by default; binding it to A takes it over
+    assert_eq!(
+        bindings.gamepad_action(GamepadInput::Button(GamepadButton::South)),
+        a
+    );
+    assert_eq!(
+        bindings.gamepad_action(GamepadInput::Axis(GamepadAxis::RightStickX, true)),
+        a
+    );
+    assert_eq!(
+        bindings.gamepad_action(GamepadInput::Button(GamepadButton::East)),
+        None
+    );
+}
+
+#[test]
+fn test_bindings_validation() {
+    let errors = Bindings::from_toml(
+        r#"
+        [keyboard]
+        jump = ["Space"]
+        a = ["NotAKey"]
+        b = ["X"]
+        start = ["X"]
+
+        [gamepad]
+        select = ["LeftStickZ+"]
+        "#,
+    )
+    .unwrap_err();
+    assert_eq!(errors.len(), 4);
+    assert!(errors[0].contains("unknown action \"jump\""));
+    assert!(errors[1].contains("unknown input \"NotAKey\""));
+    assert!(errors[2].contains("\"X\" is bound to both b and start"));
+    assert!(errors[3].contains("unknown input \"LeftStickZ+\""));
+
+    assert!(Bindings::from_toml("[mouse]").is_err());
+    assert!(Bindings::from_toml("keyboard = 3").is_err());
+    assert_eq!(Bindings::from_toml("").unwrap(), Bindings::default());
+}
+
+#[test]
+fn test_axis_edges() {
+    let mut state = AxisState::default();
+    let right = GamepadInput::Axis(GamepadAxis::LeftStickX, true);
+    let left = GamepadInput::Axis(GamepadAxis::LeftStickX, false);
+
+    assert!(state.update(GamepadAxis::LeftStickX, 0.2).is_empty());
+    assert_eq!(
+        state.update(GamepadAxis::LeftStickX, 0.8),
+        vec![(right, true)]
+    );
+    assert!(state.update(GamepadAxis::LeftStickX, 0.9).is_empty());
+    assert_eq!(
+        state.update(GamepadAxis::LeftStickX, -1.0),
+        vec![(right, false), (left, true)]
+    );
+    assert_eq!(
+        state.update(GamepadAxis::LeftStickX, 0.0),
+        vec![(left, false)]
+    );
+}
@@ -0,0 +1,186 @@
+use super::{
+    header::CartridgeHeader,
+    mbc::{MemoryBankController, MBC1, MBC2, MBC3, MBC5},
+    rtc::{SystemTimeSource, TimeSource, RTC, RTC_SAVE_LEN},
+};
+
+/*
+
+Cartridge
+
+0x0000 - 0x7FFF: ROM, banked by the MBC
+0xA000 - 0xBFFF: External RAM, banked by the MBC
+
+The MBC and RAM size are selected from the cartridge header; ROMs too small
+to have a header are treated as ROM only.
+
+*/
+
+#[derive(Debug)]
+pub struct Cartridge {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mbc: MemoryBankController,
+    header: Option<CartridgeHeader>,
+}
+
+impl Cartridge {
+    pub fn new(rom: Vec<u8>) -> Cartridge {
+        Cartridge::with_time_source(rom, Box::new(SystemTimeSource))
+    }
+
+    // The time source drives the MBC3 real time clock
+    pub fn with_time_source(rom: Vec<u8>, time_source: Box<dyn TimeSource>) -> Cartridge {
+        let header = CartridgeHeader::from_rom(&rom).ok();
+        let cartridge_type = header.as_ref().map_or(0, |header| header.cartridge_type);
+        let mbc = match cartridge_type {
+            0x01..=0x03 => MemoryBankController::MBC1(MBC1::new(Cartridge::is_multicart(&rom))),
+            0x05 | 0x06 => MemoryBankController::MBC2(MBC2::new()),
+            // MBC3+TIMER(+RAM+BATTERY)
+            0x0F | 0x10 => MemoryBankController::MBC3(MBC3::new(Some(RTC::new(time_source)))),
+            0x11..=0x13 => MemoryBankController::MBC3(MBC3::new(None)),
+            0x19..=0x1B => MemoryBankController::MBC5(MBC5::new(false)),
+            0x1C..=0x1E => MemoryBankController::MBC5(MBC5::new(true)),
+            0x00 | 0x08 | 0x09 => MemoryBankController::NoMBC,
+            _ => {
+                println!("Warning: unsupported cartridge type 0x{cartridge_type:02X}, treating as ROM only");
+                MemoryBankController::NoMBC
+            }
+        };
+        let ram_size = match mbc {
+            // MBC2 has 512x4 bits of RAM built in, regardless of the header
+            MemoryBankController::MBC2(_) => 0x200,
+            _ => header.as_ref().map_or(0, |header| header.ram_size()),
+        };
+
+        Cartridge {
+            rom,
+            ram: vec![0; ram_size],
+            mbc,
+            header,
+        }
+    }
+
+    pub fn read_rom(&self, addr: u16) -> u8 {
+        self.mbc.read_rom(&self.rom, addr)
+    }
+
+    pub fn write_rom(&mut self, addr: u16, value: u8) {
+        self.mbc.write_rom(addr, value);
+    }
+
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        self.mbc.read_ram(&self.ram, addr)
+    }
+
+    pub fn write_ram(&mut self, addr: u16, value: u8) {
+        self.mbc.write_ram(&mut self.ram, addr, value);
+    }
+
+    pub fn header(&self) -> Option<&CartridgeHeader> {
+        self.header.as_ref()
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.header
+            .as_ref()
+            .is_some_and(|header| header.has_battery())
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        matches!(
+            self.mbc,
+            MemoryBankController::MBC3(MBC3 { rtc: Some(_), .. })
+        )
+    }
+
+    // Battery RAM followed by the RTC trailer for carts that have a clock
+    pub fn save_data(&mut self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let MemoryBankController::MBC3(MBC3 { rtc: Some(rtc), .. }) = &mut self.mbc {
+            data.extend(rtc.to_save_bytes());
+        }
+        data
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let ram_len = self.ram.len().min(data.len());
+        self.ram[..ram_len].copy_from_slice(&data[..ram_len]);
+
+        if let MemoryBankController::MBC3(MBC3 { rtc: Some(rtc), .. }) = &mut self.mbc {
+            let trailer = &data[ram_len..];
+            if !trailer.is_empty() && !rtc.load_save_bytes(trailer) {
+                println!(
+                    "Warning: ignoring {} byte RTC trailer, expected {RTC_SAVE_LEN}",
+                    trailer.len()
+                );
+            }
+        }
+    }
+
+    fn is_multicart(rom: &[u8]) -> bool {
+        // MBC1M multicarts are 1 MiB and repeat the Nintendo logo at the start
+        // of the second game (bank 0x10)
+        const LOGO: std::ops::Range<usize> = 0x0104..0x0134;
+        const SECOND_GAME: usize = 0x10 * 0x4000;
+        rom.len() == 0x100000 && rom[LOGO] == rom[SECOND_GAME + LOGO.start..SECOND_GAME + LOGO.end]
+    }
+}
+
+#[test]
+fn test_cartridge_type() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0147] = 0x03;
+    rom[0x0149] = 0x03;
+    let cartridge = Cartridge::new(rom);
+    assert!(matches!(cartridge.mbc, MemoryBankController::MBC1(_)));
+    assert_eq!(cartridge.ram.len(), 0x8000);
+
+    let mut rom = vec![0; 0x8000];
+    rom[0x0147] = 0x06;
+    let cartridge = Cartridge::new(rom);
+    assert!(matches!(cartridge.mbc, MemoryBankController::MBC2(_)));
+    assert_eq!(cartridge.ram.len(), 0x200);
+}
+
+#[test]
+fn test_save_data_rtc_trailer() {
+    use super::rtc::ManualTimeSource;
+
+    let time = ManualTimeSource::default();
+    let mut rom = vec![0; 0x8000];
+    rom[0x0147] = 0x10;
+    rom[0x0149] = 0x02;
+    let mut cartridge = Cartridge::with_time_source(rom.clone(), Box::new(time.clone()));
+    cartridge.write_rom(0x0000, 0x0A);
+    cartridge.write_ram(0xA010, 0x77);
+
+    let data = cartridge.save_data();
+    assert_eq!(data.len(), 0x2000 + RTC_SAVE_LEN);
+
+    let mut restored = Cartridge::with_time_source(rom, Box::new(time));
+    restored.load_save_data(&data);
+    restored.write_rom(0x0000, 0x0A);
+    assert_eq!(restored.read_ram(0xA010), 0x77);
+}
+
+#[test]
+fn test_ram_enable_gating() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0147] = 0x1B;
+    rom[0x0149] = 0x02;
+    let mut cartridge = Cartridge::new(rom);
+    assert!(cartridge.has_battery());
+
+    cartridge.write_ram(0xA000, 0x12);
+    assert_eq!(cartridge.read_ram(0xA000), 0xFF);
+    cartridge.write_rom(0x0000, 0x0A);
+    cartridge.write_ram(0xA000, 0x12);
+    assert_eq!(cartridge.read_ram(0xA000), 0x12);
+    cartridge.write_rom(0x0000, 0x00);
+    assert_eq!(cartridge.read_ram(0xA000), 0xFF);
+}
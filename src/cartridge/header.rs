@@ -0,0 +1,333 @@
+use std::fmt;
+
+/*
+
+Cartridge Header (0x0100 - 0x014F)
+
+0x0104 - 0x0133: Nintendo logo
+0x0134 - 0x0143: Title (0x0134 - 0x013E on CGB carts)
+0x013F - 0x0142: Manufacturer code (CGB carts)
+0x0143: CGB flag
+0x0144 - 0x0145: New licensee code
+0x0146: SGB flag
+0x0147: Cartridge type
+0x0148: ROM size
+0x0149: RAM size
+0x014A: Destination code
+0x014B: Old licensee code
+0x014C: Mask ROM version number
+0x014D: Header checksum (0x0134 - 0x014C)
+0x014E - 0x014F: Global checksum (big endian, every byte except these two)
+
+*/
+
+pub const HEADER_END: usize = 0x0150;
+
+const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CGBSupport {
+    DMG,
+    // Works on both DMG and CGB
+    Enhanced,
+    CGBOnly,
+}
+
+#[derive(Debug, Clone)]
+pub struct CartridgeHeader {
+    pub logo: [u8; 48],
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CGBSupport,
+    pub new_licensee_code: String,
+    pub sgb_support: bool,
+    pub cartridge_type: u8,
+    pub rom_size_code: u8,
+    pub ram_size_code: u8,
+    pub japanese: bool,
+    pub old_licensee_code: u8,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    pub fn from_rom(rom: &[u8]) -> Result<CartridgeHeader, String> {
+        if rom.len() < HEADER_END {
+            return Err(format!(
+                "ROM is {} bytes, too small to contain a header",
+                rom.len()
+            ));
+        }
+
+        let cgb_flag = rom[0x0143];
+        let cgb_support = match cgb_flag {
+            0xC0 => CGBSupport::CGBOnly,
+            0x80 => CGBSupport::Enhanced,
+            _ => CGBSupport::DMG,
+        };
+        // CGB carts shortened the title to make room for the manufacturer code
+        // and CGB flag
+        let (title, manufacturer_code) = match cgb_support {
+            CGBSupport::DMG => (ascii(&rom[0x0134..0x0144]), None),
+            _ => {
+                let code = &rom[0x013F..0x0143];
+                let manufacturer_code = match code.iter().all(|x| x.is_ascii_uppercase()) {
+                    true => Some(ascii(code)),
+                    false => None,
+                };
+                let title_end = match manufacturer_code {
+                    Some(_) => 0x013F,
+                    None => 0x0143,
+                };
+                (ascii(&rom[0x0134..title_end]), manufacturer_code)
+            }
+        };
+
+        Ok(CartridgeHeader {
+            logo: rom[0x0104..0x0134].try_into().unwrap(),
+            title,
+            manufacturer_code,
+            cgb_support,
+            new_licensee_code: ascii(&rom[0x0144..0x0146]),
+            sgb_support: rom[0x0146] == 0x03,
+            cartridge_type: rom[0x0147],
+            rom_size_code: rom[0x0148],
+            ram_size_code: rom[0x0149],
+            japanese: rom[0x014A] == 0x00,
+            old_licensee_code: rom[0x014B],
+            version: rom[0x014C],
+            header_checksum: rom[0x014D],
+            global_checksum: ((rom[0x014E] as u16) << 8) | rom[0x014F] as u16,
+        })
+    }
+
+    pub fn compute_header_checksum(rom: &[u8]) -> u8 {
+        rom[0x0134..=0x014C]
+            .iter()
+            .fold(0u8, |x, byte| x.wrapping_sub(*byte).wrapping_sub(1))
+    }
+
+    pub fn compute_global_checksum(rom: &[u8]) -> u16 {
+        rom.iter()
+            .enumerate()
+            .filter(|(i, _)| *i != 0x014E && *i != 0x014F)
+            .fold(0u16, |x, (_, byte)| x.wrapping_add(*byte as u16))
+    }
+
+    pub fn cartridge_type_name(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0B => "MMM01",
+            0x0C => "MMM01+RAM",
+            0x0D => "MMM01+RAM+BATTERY",
+            0x0F => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1A => "MBC5+RAM",
+            0x1B => "MBC5+RAM+BATTERY",
+            0x1C => "MBC5+RUMBLE",
+            0x1D => "MBC5+RUMBLE+RAM",
+            0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xFC => "POCKET CAMERA",
+            0xFD => "BANDAI TAMA5",
+            0xFE => "HuC3",
+            0xFF => "HuC1+RAM+BATTERY",
+            _ => "UNKNOWN",
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF
+        )
+    }
+
+    // ROM size in bytes, or None for an invalid size code
+    pub fn rom_size(&self) -> Option<usize> {
+        match self.rom_size_code {
+            0x00..=0x08 => Some(0x8000 << self.rom_size_code),
+            _ => None,
+        }
+    }
+
+    // External RAM size in bytes
+    pub fn ram_size(&self) -> usize {
+        match self.ram_size_code {
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        }
+    }
+
+    pub fn licensee_code(&self) -> String {
+        // 0x33 means the new licensee code is used instead
+        match self.old_licensee_code {
+            0x33 => self.new_licensee_code.clone(),
+            code => format!("{code:02X}"),
+        }
+    }
+
+    // Returns a warning for every check the ROM fails; an empty list means the
+    // header is valid
+    pub fn validate(&self, rom: &[u8]) -> Vec<String> {
+        let mut warnings = vec![];
+
+        if self.logo != NINTENDO_LOGO {
+            warnings.push(String::from(
+                "Nintendo logo does not match, the boot ROM will lock up",
+            ));
+        }
+
+        let header_checksum = CartridgeHeader::compute_header_checksum(rom);
+        if header_checksum != self.header_checksum {
+            warnings.push(format!(
+                "Header checksum is 0x{:02X}, expected 0x{header_checksum:02X}; the boot ROM will lock up",
+                self.header_checksum
+            ));
+        }
+
+        let global_checksum = CartridgeHeader::compute_global_checksum(rom);
+        if global_checksum != self.global_checksum {
+            warnings.push(format!(
+                "Global checksum is 0x{:04X}, expected 0x{global_checksum:04X}",
+                self.global_checksum
+            ));
+        }
+
+        match self.rom_size() {
+            None => warnings.push(format!(
+                "Unknown ROM size code 0x{:02X}",
+                self.rom_size_code
+            )),
+            Some(size) if size != rom.len() => warnings.push(format!(
+                "Header declares {size} bytes of ROM, file is {} bytes",
+                rom.len()
+            )),
+            _ => {}
+        }
+
+        if self.cartridge_type_name() == "UNKNOWN" {
+            warnings.push(format!(
+                "Unknown cartridge type 0x{:02X}",
+                self.cartridge_type
+            ));
+        }
+
+        warnings
+    }
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Title: {}", self.title)?;
+        if let Some(code) = &self.manufacturer_code {
+            writeln!(f, "Manufacturer: {code}")?;
+        }
+        writeln!(f, "Licensee: {}", self.licensee_code())?;
+        writeln!(f, "CGB: {:?}", self.cgb_support)?;
+        writeln!(f, "SGB: {}", self.sgb_support)?;
+        writeln!(
+            f,
+            "Type: 0x{:02X} ({})",
+            self.cartridge_type,
+            self.cartridge_type_name()
+        )?;
+        match self.rom_size() {
+            Some(size) => writeln!(f, "ROM size: {} KiB", size / 1024)?,
+            None => writeln!(f, "ROM size: unknown (0x{:02X})", self.rom_size_code)?,
+        }
+        writeln!(f, "RAM size: {} KiB", self.ram_size() / 1024)?;
+        writeln!(
+            f,
+            "Destination: {}",
+            match self.japanese {
+                true => "Japan",
+                false => "Overseas",
+            }
+        )?;
+        writeln!(f, "Version: {}", self.version)?;
+        writeln!(f, "Header checksum: 0x{:02X}", self.header_checksum)?;
+        write!(f, "Global checksum: 0x{:04X}", self.global_checksum)
+    }
+}
+
+fn ascii(data: &[u8]) -> String {
+    data.iter()
+        .take_while(|x| **x != 0)
+        .map(|x| match x.is_ascii_graphic() || *x == b' ' {
+            true => *x as char,
+            false => '?',
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+#[cfg(test)]
+fn create_rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+    rom[0x0134..0x0139].copy_from_slice(b"TETRA");
+    rom[0x014B] = 0x01;
+    rom[0x014D] = CartridgeHeader::compute_header_checksum(&rom);
+    let global_checksum = CartridgeHeader::compute_global_checksum(&rom);
+    rom[0x014E] = (global_checksum >> 8) as u8;
+    rom[0x014F] = (global_checksum & 0xFF) as u8;
+    rom
+}
+
+#[test]
+fn test_parse_header() {
+    let rom = create_rom();
+    let header = CartridgeHeader::from_rom(&rom).unwrap();
+
+    assert_eq!(header.title, "TETRA");
+    assert_eq!(header.cgb_support, CGBSupport::DMG);
+    assert_eq!(header.rom_size(), Some(0x8000));
+    assert_eq!(header.licensee_code(), "01");
+    assert!(header.validate(&rom).is_empty());
+}
+
+#[test]
+fn test_cgb_title() {
+    let mut rom = create_rom();
+    rom[0x0134..0x0144].copy_from_slice(b"POKEMON Y\0\0AAYE\x80");
+    let header = CartridgeHeader::from_rom(&rom).unwrap();
+
+    assert_eq!(header.title, "POKEMON Y");
+    assert_eq!(header.manufacturer_code, Some(String::from("AAYE")));
+    assert_eq!(header.cgb_support, CGBSupport::Enhanced);
+}
+
+#[test]
+fn test_bad_checksum() {
+    let mut rom = create_rom();
+    rom[0x014D] = rom[0x014D].wrapping_add(1);
+    let header = CartridgeHeader::from_rom(&rom).unwrap();
+
+    let warnings = header.validate(&rom);
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings[0].starts_with("Header checksum"));
+    assert!(CartridgeHeader::from_rom(&rom[..0x100]).is_err());
+}
@@ -0,0 +1,452 @@
+/*
+
+Memory Bank Controllers
+
+Writes to 0x0000 - 0x7FFF never reach the ROM; they are latched by the MBC
+and select which ROM bank is visible at 0x4000 - 0x7FFF (and, depending on
+the controller, 0x0000 - 0x3FFF) and which RAM bank is visible at
+0xA000 - 0xBFFF.
+
+*/
+
+use super::rtc::RTC;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+#[derive(Debug)]
+pub enum MemoryBankController {
+    NoMBC,
+    MBC1(MBC1),
+    MBC2(MBC2),
+    MBC3(MBC3),
+    MBC5(MBC5),
+}
+
+impl MemoryBankController {
+    pub fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        let bank = match self {
+            MemoryBankController::NoMBC => return read_banked(rom, 0, addr as usize),
+            MemoryBankController::MBC1(mbc) => mbc.rom_bank(addr),
+            MemoryBankController::MBC2(mbc) => mbc.rom_bank(addr),
+            MemoryBankController::MBC3(mbc) => mbc.rom_bank(addr),
+            MemoryBankController::MBC5(mbc) => mbc.rom_bank(addr),
+        };
+        read_banked(rom, bank * ROM_BANK_SIZE, addr as usize & 0x3FFF)
+    }
+
+    pub fn write_rom(&mut self, addr: u16, value: u8) {
+        match self {
+            MemoryBankController::NoMBC => {}
+            MemoryBankController::MBC1(mbc) => mbc.write(addr, value),
+            MemoryBankController::MBC2(mbc) => mbc.write(addr, value),
+            MemoryBankController::MBC3(mbc) => mbc.write(addr, value),
+            MemoryBankController::MBC5(mbc) => mbc.write(addr, value),
+        }
+    }
+
+    pub fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if let MemoryBankController::MBC3(mbc) = self {
+            if let Some(rtc) = mbc.selected_rtc() {
+                return rtc.read(mbc.ram_bank);
+            }
+        }
+        match self.ram_offset(ram, addr) {
+            Some(i) => match self {
+                // MBC2 RAM is 4 bits wide; the upper nibble is open bus
+                MemoryBankController::MBC2(_) => ram[i] | 0xF0,
+                _ => ram[i],
+            },
+            None => 0xFF,
+        }
+    }
+
+    pub fn write_ram(&mut self, ram: &mut [u8], addr: u16, value: u8) {
+        if let MemoryBankController::MBC3(mbc) = self {
+            let register = mbc.ram_bank;
+            if let Some(rtc) = mbc.selected_rtc_mut() {
+                rtc.write(register, value);
+                return;
+            }
+        }
+        if let Some(i) = self.ram_offset(ram, addr) {
+            ram[i] = match self {
+                MemoryBankController::MBC2(_) => value & 0x0F,
+                _ => value,
+            };
+        }
+    }
+
+    // Resolves 0xA000 - 0xBFFF to an index into the cartridge RAM, or None if
+    // RAM is disabled or absent
+    fn ram_offset(&self, ram: &[u8], addr: u16) -> Option<usize> {
+        if ram.is_empty() {
+            return None;
+        }
+        let relative_addr = (addr - 0xA000) as usize;
+        let (enabled, bank) = match self {
+            // ROM + RAM carts without a controller have at most one bank
+            MemoryBankController::NoMBC => (true, 0),
+            MemoryBankController::MBC1(mbc) => (mbc.ram_enable, mbc.ram_bank()),
+            MemoryBankController::MBC2(mbc) => {
+                // 512 half-bytes, mirrored across the whole region
+                return match mbc.ram_enable {
+                    true => Some(relative_addr & 0x1FF),
+                    false => None,
+                };
+            }
+            MemoryBankController::MBC3(mbc) => match mbc.ram_bank {
+                0x00..=0x07 => (mbc.ram_enable, mbc.ram_bank as usize),
+                // Unmapped bank / RTC register
+                _ => (false, 0),
+            },
+            MemoryBankController::MBC5(mbc) => (mbc.ram_enable, mbc.ram_bank as usize),
+        };
+        if !enabled {
+            return None;
+        }
+        Some((bank * RAM_BANK_SIZE + relative_addr) % ram.len())
+    }
+}
+
+fn read_banked(rom: &[u8], offset: usize, relative_addr: usize) -> u8 {
+    if rom.is_empty() {
+        return 0xFF;
+    }
+    // Bank numbers wrap around the number of banks actually present
+    rom[(offset + relative_addr) % rom.len()]
+}
+
+#[derive(Debug)]
+pub struct MBC1 {
+    ram_enable: bool,
+    // 5-bit ROM bank register (0x2000 - 0x3FFF)
+    rom_bank: u8,
+    // 2-bit register (0x4000 - 0x5FFF); upper ROM bank bits or RAM bank
+    upper_bank: u8,
+    // Banking mode (0x6000 - 0x7FFF)
+    // false: 0x0000 - 0x3FFF and RAM are fixed to bank 0
+    // true: upper_bank also applies to 0x0000 - 0x3FFF and RAM
+    mode: bool,
+    // MBC1M multicarts only wire 4 bits of the ROM bank register
+    multicart: bool,
+}
+
+impl MBC1 {
+    pub(crate) fn new(multicart: bool) -> Self {
+        MBC1 {
+            ram_enable: false,
+            rom_bank: 1,
+            upper_bank: 0,
+            mode: false,
+            multicart,
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // Writing 0 selects bank 1; this check only looks at the 5
+                // bits of the register, so 0x20/0x40/0x60 are unreachable
+                self.rom_bank = match value & 0x1F {
+                    0 => 1,
+                    bank => bank,
+                };
+            }
+            0x4000..=0x5FFF => self.upper_bank = value & 0x03,
+            _ => self.mode = value & 1 == 1,
+        }
+    }
+
+    fn upper_shift(&self) -> u8 {
+        match self.multicart {
+            true => 4,
+            false => 5,
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        let upper = (self.upper_bank << self.upper_shift()) as usize;
+        match addr {
+            0x0000..=0x3FFF => match self.mode {
+                true => upper,
+                false => 0,
+            },
+            _ => {
+                let lower = match self.multicart {
+                    true => self.rom_bank & 0x0F,
+                    false => self.rom_bank,
+                };
+                upper | lower as usize
+            }
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        match self.mode {
+            true => self.upper_bank as usize,
+            false => 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MBC2 {
+    ram_enable: bool,
+    rom_bank: u8,
+}
+
+impl MBC2 {
+    pub(crate) fn new() -> Self {
+        MBC2 {
+            ram_enable: false,
+            rom_bank: 1,
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        // Only 0x0000 - 0x3FFF is decoded; bit 8 of the address selects
+        // between the RAM enable and ROM bank registers
+        if addr >= 0x4000 {
+            return;
+        }
+        match (addr >> 8) & 1 {
+            0 => self.ram_enable = value & 0x0F == 0x0A,
+            _ => {
+                self.rom_bank = match value & 0x0F {
+                    0 => 1,
+                    bank => bank,
+                }
+            }
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MBC3 {
+    // Also enables access to the RTC registers
+    ram_enable: bool,
+    rom_bank: u8,
+    // 0x00 - 0x07: RAM bank, 0x08 - 0x0C: RTC register
+    ram_bank: u8,
+    pub rtc: Option<RTC>,
+}
+
+impl MBC3 {
+    pub(crate) fn new(rtc: Option<RTC>) -> Self {
+        MBC3 {
+            ram_enable: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc,
+        }
+    }
+
+    fn selected_rtc(&self) -> Option<&RTC> {
+        match (self.ram_enable, self.ram_bank) {
+            (true, 0x08..=0x0C) => self.rtc.as_ref(),
+            _ => None,
+        }
+    }
+
+    fn selected_rtc_mut(&mut self) -> Option<&mut RTC> {
+        match (self.ram_enable, self.ram_bank) {
+            (true, 0x08..=0x0C) => self.rtc.as_mut(),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = match value & 0x7F {
+                    0 => 1,
+                    bank => bank,
+                }
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(value);
+                }
+            }
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MBC5 {
+    ram_enable: bool,
+    // 9-bit ROM bank; unlike the other controllers bank 0 can be selected
+    rom_bank: u16,
+    ram_bank: u8,
+    // On rumble carts bit 3 of the RAM bank register drives the motor
+    has_rumble: bool,
+    pub rumble: bool,
+}
+
+impl MBC5 {
+    pub(crate) fn new(has_rumble: bool) -> Self {
+        MBC5 {
+            ram_enable: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable = value == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((value as u16 & 1) << 8),
+            0x4000..=0x5FFF => match self.has_rumble {
+                true => {
+                    self.rumble = (value >> 3) & 1 == 1;
+                    self.ram_bank = value & 0x07;
+                }
+                false => self.ram_bank = value & 0x0F,
+            },
+            _ => {}
+        }
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+fn create_rom(banks: usize) -> Vec<u8> {
+    // Every byte of a bank holds that bank's number
+    (0..banks * ROM_BANK_SIZE)
+        .map(|i| (i / ROM_BANK_SIZE) as u8)
+        .collect()
+}
+
+#[test]
+fn test_mbc1_banking() {
+    let rom = create_rom(128);
+    let mut mbc = MemoryBankController::MBC1(MBC1::new(false));
+
+    assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+    mbc.write_rom(0x2000, 0);
+    assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+    mbc.write_rom(0x2000, 0x13);
+    mbc.write_rom(0x4000, 0x2);
+    assert_eq!(mbc.read_rom(&rom, 0x7FFF), 0x53);
+    // Bank 0 area only follows the upper bits in mode 1
+    assert_eq!(mbc.read_rom(&rom, 0x0000), 0);
+    mbc.write_rom(0x6000, 1);
+    assert_eq!(mbc.read_rom(&rom, 0x0000), 0x40);
+}
+
+#[test]
+fn test_mbc1_multicart() {
+    let rom = create_rom(64);
+    let mut mbc = MemoryBankController::MBC1(MBC1::new(true));
+
+    mbc.write_rom(0x2000, 0x12);
+    mbc.write_rom(0x4000, 0x1);
+    assert_eq!(mbc.read_rom(&rom, 0x4000), 0x12);
+    mbc.write_rom(0x6000, 1);
+    assert_eq!(mbc.read_rom(&rom, 0x0000), 0x10);
+}
+
+#[test]
+fn test_mbc1_ram_enable() {
+    let mut ram = vec![0; 0x8000];
+    let mut mbc = MemoryBankController::MBC1(MBC1::new(false));
+
+    mbc.write_ram(&mut ram, 0xA000, 0x42);
+    assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_rom(0x6000, 1);
+    mbc.write_rom(0x4000, 2);
+    mbc.write_ram(&mut ram, 0xA000, 0x42);
+    assert_eq!(mbc.read_ram(&ram, 0xA000), 0x42);
+    assert_eq!(ram[0x4000], 0x42);
+}
+
+#[test]
+fn test_mbc2() {
+    let rom = create_rom(16);
+    let mut ram = vec![0; 0x200];
+    let mut mbc = MemoryBankController::MBC2(MBC2::new());
+
+    // Bit 8 clear: RAM enable, bit 8 set: ROM bank
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_rom(0x0100, 0x05);
+    assert_eq!(mbc.read_rom(&rom, 0x4000), 5);
+
+    mbc.write_ram(&mut ram, 0xA003, 0xAB);
+    assert_eq!(mbc.read_ram(&ram, 0xA003), 0xFB);
+    assert_eq!(mbc.read_ram(&ram, 0xA203), 0xFB);
+}
+
+#[test]
+fn test_mbc5() {
+    let rom = create_rom(512);
+    let mut mbc = MemoryBankController::MBC5(MBC5::new(true));
+
+    mbc.write_rom(0x2000, 0);
+    assert_eq!(mbc.read_rom(&rom, 0x4000), 0);
+    mbc.write_rom(0x2000, 0x05);
+    mbc.write_rom(0x3000, 0x01);
+    // Bank 0x105
+    assert_eq!(rom[0x105 * ROM_BANK_SIZE], 5);
+    assert_eq!(mbc.read_rom(&rom, 0x4000), 5);
+
+    mbc.write_rom(0x4000, 0b1010);
+    match mbc {
+        MemoryBankController::MBC5(mbc5) => {
+            assert!(mbc5.rumble);
+            assert_eq!(mbc5.ram_bank, 2);
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_mbc3_rtc_registers() {
+    use super::rtc::ManualTimeSource;
+
+    let time = ManualTimeSource::default();
+    let mut ram = vec![0; 0x8000];
+    let mut mbc = MemoryBankController::MBC3(MBC3::new(Some(RTC::new(Box::new(time.clone())))));
+
+    mbc.write_rom(0x0000, 0x0A);
+    mbc.write_rom(0x4000, 0x01);
+    mbc.write_ram(&mut ram, 0xA000, 0x11);
+    assert_eq!(ram[0x2000], 0x11);
+
+    // Select the minutes register
+    mbc.write_rom(0x4000, 0x09);
+    mbc.write_ram(&mut ram, 0xA000, 5);
+    time.advance(120);
+    mbc.write_rom(0x6000, 0x00);
+    mbc.write_rom(0x6000, 0x01);
+    assert_eq!(mbc.read_ram(&ram, 0xA000), 7);
+    assert_eq!(ram[0x2000], 0x11);
+}
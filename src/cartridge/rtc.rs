@@ -0,0 +1,287 @@
+use std::fmt::Debug;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+
+MBC3 Real Time Clock
+
+Selected by writing 0x08 - 0x0C to 0x4000 - 0x5FFF, then read/written
+through 0xA000 - 0xBFFF:
+
+0x08: Seconds (0-59)
+0x09: Minutes (0-59)
+0x0A: Hours (0-23)
+0x0B: Lower 8 bits of the day counter
+0x0C: Bit 0: Upper bit of the day counter
+      Bit 6: Halt (0=Active, 1=Stop timer)
+      Bit 7: Day counter carry
+
+Writing 0x00 then 0x01 to 0x6000 - 0x7FFF latches the current time into the
+registers the game reads.
+
+The clock is kept in sync with the host through a TimeSource, so it keeps
+running while the emulator is closed.
+
+*/
+
+pub trait TimeSource: Debug + Send {
+    // Seconds since the unix epoch
+    fn now(&self) -> u64;
+}
+
+#[derive(Debug)]
+pub struct SystemTimeSource;
+
+impl TimeSource for SystemTimeSource {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+// Length of the RTC trailer appended to battery RAM by VBA/BGB
+pub const RTC_SAVE_LEN: usize = 48;
+// Older saves store a 32-bit timestamp instead
+const RTC_SAVE_LEN_32: usize = 44;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct RTCRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halt: bool,
+    day_carry: bool,
+}
+
+impl RTCRegisters {
+    fn read(&self, register: u8) -> u8 {
+        match register {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => (self.days & 0xFF) as u8,
+            _ => {
+                ((self.days >> 8) as u8 & 1)
+                    | ((self.halt as u8) << 6)
+                    | ((self.day_carry as u8) << 7)
+            }
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0x08 => self.seconds = value & 0x3F,
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours = value & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | value as u16,
+            _ => {
+                self.days = (self.days & 0xFF) | ((value as u16 & 1) << 8);
+                self.halt = (value >> 6) & 1 == 1;
+                self.day_carry = (value >> 7) & 1 == 1;
+            }
+        }
+    }
+
+    fn advance(&mut self, elapsed: u64) {
+        let total = elapsed
+            + self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 3600
+            + self.days as u64 * 86400;
+
+        self.seconds = (total % 60) as u8;
+        self.minutes = ((total / 60) % 60) as u8;
+        self.hours = ((total / 3600) % 24) as u8;
+        let days = total / 86400;
+        if days > 0x1FF {
+            // The carry stays set until the game clears it
+            self.day_carry = true;
+        }
+        self.days = (days % 0x200) as u16;
+    }
+
+    fn to_save_bytes(self) -> [u32; 5] {
+        [0x08, 0x09, 0x0A, 0x0B, 0x0C].map(|register| self.read(register) as u32)
+    }
+
+    fn from_save_bytes(data: &[u8]) -> RTCRegisters {
+        let mut registers = RTCRegisters::default();
+        for (i, register) in (0x08..=0x0C).enumerate() {
+            registers.write(register, data[i * 4]);
+        }
+        registers
+    }
+}
+
+#[derive(Debug)]
+pub struct RTC {
+    registers: RTCRegisters,
+    latched: RTCRegisters,
+    // Set by writing 0x00 to the latch register; the following 0x01 latches
+    latch_armed: bool,
+    // Host time the registers were last brought up to date
+    last_update: u64,
+    time_source: Box<dyn TimeSource>,
+}
+
+impl RTC {
+    pub fn new(time_source: Box<dyn TimeSource>) -> RTC {
+        let last_update = time_source.now();
+        RTC {
+            registers: RTCRegisters::default(),
+            latched: RTCRegisters::default(),
+            latch_armed: false,
+            last_update,
+            time_source,
+        }
+    }
+
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched.read(register)
+    }
+
+    pub fn write(&mut self, register: u8, value: u8) {
+        self.update();
+        self.registers.write(register, value);
+        self.latched.write(register, value);
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 0x01 {
+            self.update();
+            self.latched = self.registers;
+        }
+        self.latch_armed = value == 0x00;
+    }
+
+    fn update(&mut self) {
+        let now = self.time_source.now();
+        // Host clocks can go backwards; never rewind the RTC
+        let elapsed = now.saturating_sub(self.last_update);
+        self.last_update = now;
+        if !self.registers.halt {
+            self.registers.advance(elapsed);
+        }
+    }
+
+    // Serializes to the 48-byte VBA/BGB format: the current and latched
+    // registers as 32-bit little endian words, followed by a 64-bit unix
+    // timestamp
+    pub fn to_save_bytes(&mut self) -> Vec<u8> {
+        self.update();
+        let mut data = Vec::with_capacity(RTC_SAVE_LEN);
+        for word in self
+            .registers
+            .to_save_bytes()
+            .into_iter()
+            .chain(self.latched.to_save_bytes())
+        {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(&self.last_update.to_le_bytes());
+        data
+    }
+
+    // Restores from either the 48 or 44-byte format, then catches up on the
+    // time that passed since the save was written
+    pub fn load_save_bytes(&mut self, data: &[u8]) -> bool {
+        let timestamp = match data.len() {
+            RTC_SAVE_LEN => u64::from_le_bytes(data[40..48].try_into().unwrap()),
+            RTC_SAVE_LEN_32 => u32::from_le_bytes(data[40..44].try_into().unwrap()) as u64,
+            _ => return false,
+        };
+        self.registers = RTCRegisters::from_save_bytes(&data[0..20]);
+        self.latched = RTCRegisters::from_save_bytes(&data[20..40]);
+        self.last_update = timestamp;
+        self.update();
+        true
+    }
+}
+
+#[cfg(test)]
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub struct ManualTimeSource(pub Arc<AtomicU64>);
+
+#[cfg(test)]
+impl ManualTimeSource {
+    pub fn advance(&self, seconds: u64) {
+        self.0.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl TimeSource for ManualTimeSource {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[test]
+fn test_rtc_latch() {
+    let time = ManualTimeSource::default();
+    let mut rtc = RTC::new(Box::new(time.clone()));
+
+    time.advance(90061);
+    // Not latched yet
+    assert_eq!(rtc.read(0x08), 0);
+    rtc.write_latch(0x00);
+    rtc.write_latch(0x01);
+    assert_eq!(rtc.read(0x08), 1);
+    assert_eq!(rtc.read(0x09), 1);
+    assert_eq!(rtc.read(0x0A), 1);
+    assert_eq!(rtc.read(0x0B), 1);
+
+    // A lone 0x01 doesn't latch
+    time.advance(5);
+    rtc.write_latch(0x01);
+    assert_eq!(rtc.read(0x08), 1);
+}
+
+#[test]
+fn test_rtc_halt_and_carry() {
+    let time = ManualTimeSource::default();
+    let mut rtc = RTC::new(Box::new(time.clone()));
+
+    rtc.write(0x0C, 0b0100_0000);
+    time.advance(1000);
+    rtc.write_latch(0x00);
+    rtc.write_latch(0x01);
+    assert_eq!(rtc.read(0x08), 0);
+
+    // Day 511 rolls over into the carry bit
+    rtc.write(0x0B, 0xFF);
+    rtc.write(0x0C, 0x01);
+    time.advance(86400);
+    rtc.write_latch(0x00);
+    rtc.write_latch(0x01);
+    assert_eq!(rtc.read(0x0B), 0);
+    assert_eq!(rtc.read(0x0C), 0b1000_0000);
+}
+
+#[test]
+fn test_rtc_save_catch_up() {
+    let time = ManualTimeSource::default();
+    time.advance(1_000_000);
+    let mut rtc = RTC::new(Box::new(time.clone()));
+    rtc.write(0x09, 30);
+    let data = rtc.to_save_bytes();
+    assert_eq!(data.len(), RTC_SAVE_LEN);
+
+    // The emulator is closed for an hour
+    time.advance(3600);
+    let mut restored = RTC::new(Box::new(time.clone()));
+    assert!(restored.load_save_bytes(&data));
+    restored.write_latch(0x00);
+    restored.write_latch(0x01);
+    assert_eq!(restored.read(0x09), 30);
+    assert_eq!(restored.read(0x0A), 1);
+}
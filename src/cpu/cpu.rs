@@ -1,48 +1,107 @@
-use std::sync::mpsc::Sender;
-
 use super::{
     instruction::{
         ArithmeticTarget, D8Operation, Instruction, JumpTest, LoadByteSource, LoadByteTarget,
         LoadType, SixteenBitArithmeticTarget, StackTarget,
     },
+    memory_interface::MemoryInterface,
     registers::Registers,
 };
-use crate::request_response::{Bus, Request};
+use crate::{
+    interrupt::{InterruptFlag, DISPATCH_T, IE_ADDR, IF_ADDR},
+    joypad::JOYP_ADDR,
+    request_response::{Bus, BusError},
+    timer::DIV_ADDR,
+};
 
 #[derive(Debug)]
-pub struct CPU {
+pub struct CPU<M = Bus> {
     pub registers: Registers,
     pub pc: u16,
     pub sp: u16,
     is_halted: bool,
     is_stopped: bool,
+    // Set when HALT is executed with IME=0 and an interrupt already pending;
+    // the byte after HALT is then read twice
+    halt_bug: bool,
     m: u16,
     t: u16,
     interrupt: Interrupt,
-    bus: Bus,
+    bus: M,
 }
 
-impl CPU {
-    pub fn new(request_sender: Sender<Request>) -> Self {
+impl<M: MemoryInterface> CPU<M> {
+    pub fn new(bus: M) -> Self {
         CPU {
             registers: Registers::new(),
             pc: 0,
             sp: 0,
-            bus: Bus { request_sender },
+            bus,
             is_halted: false,
             is_stopped: false,
+            halt_bug: false,
             m: 0,
             t: 0,
             interrupt: Interrupt::Enabled,
         }
     }
 
-    pub fn step(&mut self) -> u8 {
-        if self.pc == 0x0100 {
-            self.bus.load_rom();
+    pub fn bus(&self) -> &M {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut M {
+        &mut self.bus
+    }
+
+    // Starts execution at the cartridge entry point with the register values
+    // the DMG boot ROM leaves behind
+    pub fn skip_boot(&mut self) {
+        self.registers.set_af(0x01B0);
+        self.registers.set_bc(0x0013);
+        self.registers.set_de(0x00D8);
+        self.registers.set_hl(0x014D);
+        self.sp = 0xFFFE;
+        self.pc = 0x0100;
+        self.interrupt = Interrupt::Disabled;
+    }
+
+    // Fails when the memory thread can't serve one of the CPU's requests
+    pub fn step(&mut self) -> Result<u8, BusError> {
+        let t = self.step_instruction()?;
+        // Peripherals clocked by the CPU (timer) advance by the same t-cycles,
+        // less any the bus already ticked on the step's accesses
+        self.bus.tick(t)?;
+        Ok(t)
+    }
+
+    fn step_instruction(&mut self) -> Result<u8, BusError> {
+        if self.is_stopped {
+            // Low power mode; only a joypad line going low wakes the CPU
+            if self.bus.read_internal(JOYP_ADDR)? & 0x0F == 0x0F {
+                return Ok(self.idle());
+            }
+            self.is_stopped = false;
+        }
+
+        if self.is_halted {
+            // HALT idles until IE & IF is non-zero, regardless of IME
+            if self.pending_interrupts()? == 0 {
+                return Ok(self.idle());
+            }
+            self.is_halted = false;
+        }
+
+        if let Some(t) = self.handle_interrupts()? {
+            return Ok(t);
         }
 
-        let mut instruction_byte = self.bus.read_byte(self.pc);
+        let mut instruction_byte = self.bus.read_byte(self.pc)?;
+        if self.halt_bug {
+            // PC fails to increment after the opcode fetch, so the operands
+            // are read starting from the opcode itself
+            self.halt_bug = false;
+            self.pc = self.pc.wrapping_sub(1);
+        }
         let prefixed = instruction_byte == 0xCB;
         let (mut toggle_interrupt, interrupt_state) = match self.interrupt {
             Interrupt::Transition(state) => (true, state),
@@ -51,12 +110,12 @@ impl CPU {
         if prefixed {
             self.t = self.t.wrapping_add(4);
             self.m = self.m.wrapping_add(1);
-            instruction_byte = self.bus.read_byte(self.pc.wrapping_add(1));
+            instruction_byte = self.bus.read_byte(self.pc.wrapping_add(1))?;
         }
 
         let (next_pc, t) =
             if let Some(instruction) = Instruction::from_byte(instruction_byte, prefixed) {
-                self.execute(instruction)
+                self.execute(instruction)?
             } else {
                 let description = format!(
                     "0x{}{:x}",
@@ -86,16 +145,49 @@ impl CPU {
         self.t = self.t.wrapping_add(t as u16);
         self.m = self.m.wrapping_add((t as u16) / 4);
         self.pc = next_pc;
-        return t;
+        return Ok(t);
         // self.bus.gpu.step(self.t); TODO: Add GPU step
-        // self.bus.write_byte(0xFF44, self.bus.gpu.line); TODO: Add GPU writing to 0xFF44
+        // self.bus.write_byte(0xFF44, self.bus.gpu.line)?; TODO: Add GPU writing to 0xFF44
     }
 
-    fn execute(&mut self, instruction: Instruction) -> (u16, u8) {
-        if self.is_halted {
-            return (self.pc, 0);
+    fn handle_interrupts(&mut self) -> Result<Option<u8>, BusError> {
+        // Only dispatches when IME is set; EI's delayed enable is still in
+        // Transition and is ignored here
+        if !matches!(self.interrupt, Interrupt::Enabled) {
+            return Ok(None);
         }
-        match instruction {
+
+        let Some(flag) = InterruptFlag::from_pending(self.pending_interrupts()?) else {
+            return Ok(None);
+        };
+
+        // Acknowledge the interrupt: IME is cleared, the IF bit is reset and
+        // the current PC is pushed before jumping to the vector
+        self.interrupt = Interrupt::Disabled;
+        let requested = self.bus.read_internal(IF_ADDR)?;
+        self.bus.write_internal(IF_ADDR, requested & !flag.bit())?;
+        self.push(self.pc)?;
+        self.pc = flag.vector();
+
+        self.t = self.t.wrapping_add(DISPATCH_T as u16);
+        self.m = self.m.wrapping_add((DISPATCH_T as u16) / 4);
+        Ok(Some(DISPATCH_T))
+    }
+
+    fn pending_interrupts(&mut self) -> Result<u8, BusError> {
+        Ok(self.bus.read_internal(IE_ADDR)? & self.bus.read_internal(IF_ADDR)? & 0x1F)
+    }
+
+    fn idle(&mut self) -> u8 {
+        // Halted/stopped CPUs still advance in 4 t-cycle increments so the
+        // other processing units keep running
+        self.t = self.t.wrapping_add(4);
+        self.m = self.m.wrapping_add(1);
+        4
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<(u16, u8), BusError> {
+        let result = match instruction {
             Instruction::ADD(target) => match target {
                 ArithmeticTarget::A => {
                     let value = self.registers.a;
@@ -140,7 +232,7 @@ impl CPU {
                     (self.pc.wrapping_add(1), 4)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.add(value);
                     self.registers.a = new_value;
                     (self.pc.wrapping_add(1), 8)
@@ -216,7 +308,7 @@ impl CPU {
                     (self.pc.wrapping_add(1), 4)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.adc(value);
                     self.registers.a = new_value;
                     (self.pc.wrapping_add(1), 8)
@@ -266,7 +358,7 @@ impl CPU {
                     (self.pc.wrapping_add(1), 4)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.sub(value);
                     self.registers.a = new_value;
                     (self.pc.wrapping_add(1), 8)
@@ -316,7 +408,7 @@ impl CPU {
                     (self.pc.wrapping_add(1), 4)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.sbc(value);
                     self.registers.a = new_value;
                     (self.pc.wrapping_add(1), 8)
@@ -366,7 +458,7 @@ impl CPU {
                     (self.pc.wrapping_add(1), 4)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.and(value);
                     self.registers.a = new_value;
                     (self.pc.wrapping_add(1), 8)
@@ -416,7 +508,7 @@ impl CPU {
                     (self.pc.wrapping_add(1), 4)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.or(value);
                     self.registers.a = new_value;
                     (self.pc.wrapping_add(1), 8)
@@ -466,7 +558,7 @@ impl CPU {
                     (self.pc.wrapping_add(1), 4)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.xor(value, false);
                     self.registers.a = new_value;
                     (self.pc.wrapping_add(1), 8)
@@ -509,7 +601,7 @@ impl CPU {
                     (self.pc.wrapping_add(1), 4)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     self.sub(value);
                     (self.pc.wrapping_add(1), 8)
                 }
@@ -558,9 +650,9 @@ impl CPU {
                     (self.pc.wrapping_add(1), 4)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.inc(value);
-                    self.bus.write_byte(self.registers.get_hl(), new_value);
+                    self.bus.write_byte(self.registers.get_hl(), new_value)?;
                     (self.pc.wrapping_add(1), 12)
                 }
             },
@@ -629,9 +721,9 @@ impl CPU {
                     (self.pc.wrapping_add(1), 4)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.dec(value);
-                    self.bus.write_byte(self.registers.get_hl(), new_value);
+                    self.bus.write_byte(self.registers.get_hl(), new_value)?;
                     (self.pc.wrapping_add(1), 12)
                 }
             },
@@ -747,7 +839,7 @@ impl CPU {
                     (self.pc.wrapping_add(2), 8)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     self.bit(value, n);
                     (self.pc.wrapping_add(2), 12)
                 }
@@ -796,9 +888,9 @@ impl CPU {
                     (self.pc.wrapping_add(2), 8)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.res(value, n);
-                    self.bus.write_byte(self.registers.get_hl(), new_value);
+                    self.bus.write_byte(self.registers.get_hl(), new_value)?;
                     (self.pc.wrapping_add(2), 16)
                 }
             },
@@ -846,9 +938,9 @@ impl CPU {
                     (self.pc.wrapping_add(2), 8)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.set(value, n);
-                    self.bus.write_byte(self.registers.get_hl(), new_value);
+                    self.bus.write_byte(self.registers.get_hl(), new_value)?;
                     (self.pc.wrapping_add(2), 16)
                 }
             },
@@ -896,9 +988,9 @@ impl CPU {
                     (self.pc.wrapping_add(2), 8)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.inc(value);
-                    self.bus.write_byte(self.registers.get_hl(), new_value);
+                    self.bus.write_byte(self.registers.get_hl(), new_value)?;
                     (self.pc.wrapping_add(2), 16)
                 }
             },
@@ -948,9 +1040,9 @@ impl CPU {
                     (self.pc.wrapping_add(2), 8)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.rr(value, false, true);
-                    self.bus.write_byte(self.registers.get_hl(), new_value);
+                    self.bus.write_byte(self.registers.get_hl(), new_value)?;
                     (self.pc.wrapping_add(2), 16)
                 }
             },
@@ -1000,9 +1092,9 @@ impl CPU {
                     (self.pc.wrapping_add(2), 8)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.rl(value, false, true);
-                    self.bus.write_byte(self.registers.get_hl(), new_value);
+                    self.bus.write_byte(self.registers.get_hl(), new_value)?;
                     (self.pc.wrapping_add(2), 16)
                 }
             },
@@ -1052,9 +1144,9 @@ impl CPU {
                     (self.pc.wrapping_add(2), 8)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.rrc(value);
-                    self.bus.write_byte(self.registers.get_hl(), new_value);
+                    self.bus.write_byte(self.registers.get_hl(), new_value)?;
                     (self.pc.wrapping_add(2), 16)
                 }
             },
@@ -1104,9 +1196,9 @@ impl CPU {
                     (self.pc.wrapping_add(2), 8)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.rlc(value);
-                    self.bus.write_byte(self.registers.get_hl(), new_value);
+                    self.bus.write_byte(self.registers.get_hl(), new_value)?;
                     (self.pc.wrapping_add(2), 16)
                 }
             },
@@ -1154,9 +1246,9 @@ impl CPU {
                     (self.pc.wrapping_add(2), 8)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.sra(value);
-                    self.bus.write_byte(self.registers.get_hl(), new_value);
+                    self.bus.write_byte(self.registers.get_hl(), new_value)?;
                     (self.pc.wrapping_add(2), 16)
                 }
             },
@@ -1204,9 +1296,9 @@ impl CPU {
                     (self.pc.wrapping_add(2), 8)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.sla(value);
-                    self.bus.write_byte(self.registers.get_hl(), new_value);
+                    self.bus.write_byte(self.registers.get_hl(), new_value)?;
                     (self.pc.wrapping_add(2), 16)
                 }
             },
@@ -1254,56 +1346,56 @@ impl CPU {
                     (self.pc.wrapping_add(2), 8)
                 }
                 ArithmeticTarget::HL => {
-                    let value = self.bus.read_byte(self.registers.get_hl());
+                    let value = self.bus.read_byte(self.registers.get_hl())?;
                     let new_value = self.swap(value);
-                    self.bus.write_byte(self.registers.get_hl(), new_value);
+                    self.bus.write_byte(self.registers.get_hl(), new_value)?;
                     (self.pc.wrapping_add(2), 16)
                 }
             },
             Instruction::ImmedieteArithmetic(operation) => match operation {
                 D8Operation::ADD => {
-                    let value = self.bus.read_byte(self.pc + 1);
+                    let value = self.bus.read_byte(self.pc + 1)?;
                     let new_value = self.add(value);
                     self.registers.a = new_value;
                     (self.pc.wrapping_add(2), 8)
                 }
                 D8Operation::ADC => {
-                    let value = self.bus.read_byte(self.pc + 1);
+                    let value = self.bus.read_byte(self.pc + 1)?;
                     let new_value = self.adc(value);
                     self.registers.a = new_value;
                     (self.pc.wrapping_add(2), 8)
                 }
                 D8Operation::AND => {
-                    let value = self.bus.read_byte(self.pc + 1);
+                    let value = self.bus.read_byte(self.pc + 1)?;
                     let new_value = self.and(value);
                     self.registers.a = new_value;
                     (self.pc.wrapping_add(2), 8)
                 }
                 D8Operation::CP => {
-                    let value = self.bus.read_byte(self.pc + 1);
+                    let value = self.bus.read_byte(self.pc + 1)?;
                     self.sub(value);
                     (self.pc.wrapping_add(2), 8)
                 }
                 D8Operation::OR => {
-                    let value = self.bus.read_byte(self.pc + 1);
+                    let value = self.bus.read_byte(self.pc + 1)?;
                     let new_value = self.or(value);
                     self.registers.a = new_value;
                     (self.pc.wrapping_add(2), 8)
                 }
                 D8Operation::SBC => {
-                    let value = self.bus.read_byte(self.pc + 1);
+                    let value = self.bus.read_byte(self.pc + 1)?;
                     let new_value = self.sbc(value);
                     self.registers.a = new_value;
                     (self.pc.wrapping_add(2), 8)
                 }
                 D8Operation::SUB => {
-                    let value = self.bus.read_byte(self.pc + 1);
+                    let value = self.bus.read_byte(self.pc + 1)?;
                     let new_value = self.sub(value);
                     self.registers.a = new_value;
                     (self.pc.wrapping_add(2), 8)
                 }
                 D8Operation::XOR => {
-                    let value = self.bus.read_byte(self.pc + 1);
+                    let value = self.bus.read_byte(self.pc + 1)?;
                     let new_value = self.xor(value, false);
                     self.registers.a = new_value;
                     (self.pc.wrapping_add(2), 8)
@@ -1317,7 +1409,7 @@ impl CPU {
                     JumpTest::Carry => self.registers.f.carry,
                     JumpTest::Always => true,
                 };
-                self.jump(jump_condition)
+                self.jump(jump_condition)?
             }
             Instruction::JR(test) => {
                 let jump_condition = match test {
@@ -1331,7 +1423,7 @@ impl CPU {
                 match jump_condition {
                     true => {
                         let addr = self.pc.wrapping_add(2);
-                        (self.addr8(addr, false), 12)
+                        (self.addr8(addr, false)?, 12)
                     }
                     false => (self.pc.wrapping_add(2), 8),
                 }
@@ -1350,15 +1442,15 @@ impl CPU {
                         LoadByteSource::L => self.registers.l,
                         LoadByteSource::HL => {
                             t = 8;
-                            self.bus.read_byte(self.registers.get_hl())
+                            self.bus.read_byte(self.registers.get_hl())?
                         }
                         LoadByteSource::D8 => {
                             t = 8;
-                            self.bus.read_byte(self.pc.wrapping_add(1))
+                            self.bus.read_byte(self.pc.wrapping_add(1))?
                         }
                         LoadByteSource::HLI => {
                             t = 8;
-                            let value = self.bus.read_byte(self.registers.get_hl());
+                            let value = self.bus.read_byte(self.registers.get_hl())?;
 
                             self.registers
                                 .set_hl(self.registers.get_hl().wrapping_add(1));
@@ -1367,7 +1459,7 @@ impl CPU {
                         }
                         LoadByteSource::HLD => {
                             t = 8;
-                            let value = self.bus.read_byte(self.registers.get_hl());
+                            let value = self.bus.read_byte(self.registers.get_hl())?;
 
                             self.registers
                                 .set_hl(self.registers.get_hl().wrapping_sub(1));
@@ -1376,24 +1468,24 @@ impl CPU {
                         }
                         LoadByteSource::BC => {
                             t = 8;
-                            self.bus.read_byte(self.registers.get_bc())
+                            self.bus.read_byte(self.registers.get_bc())?
                         }
                         LoadByteSource::DE => {
                             t = 8;
-                            self.bus.read_byte(self.registers.get_de())
+                            self.bus.read_byte(self.registers.get_de())?
                         }
                         LoadByteSource::RefC => {
                             t = 8;
                             let value = self.registers.c as u16;
-                            self.bus.read_byte(value.wrapping_add(0xFF00))
+                            self.bus.read_byte(value.wrapping_add(0xFF00))?
                         }
                         LoadByteSource::A16 => {
-                            let addr = self.read_next_word();
-                            self.bus.read_byte(addr)
+                            let addr = self.read_next_word()?;
+                            self.bus.read_byte(addr)?
                         }
                         LoadByteSource::A8 => {
-                            let value = self.bus.read_byte(self.pc + 1) as u16;
-                            self.bus.read_byte(value.wrapping_add(0xFF00))
+                            let value = self.bus.read_byte(self.pc + 1)? as u16;
+                            self.bus.read_byte(value.wrapping_add(0xFF00))?
                         }
                     };
                     match target {
@@ -1406,41 +1498,42 @@ impl CPU {
                         LoadByteTarget::L => self.registers.l = source_value,
                         LoadByteTarget::HL => {
                             t = 8;
-                            self.bus.write_byte(self.registers.get_hl(), source_value)
+                            self.bus.write_byte(self.registers.get_hl(), source_value)?
                         }
                         LoadByteTarget::HLI => {
                             t = 8;
-                            self.bus.write_byte(self.registers.get_hl(), source_value);
+                            self.bus.write_byte(self.registers.get_hl(), source_value)?;
                             self.registers
                                 .set_hl(self.registers.get_hl().wrapping_add(1));
                         }
                         LoadByteTarget::HLD => {
                             t = 8;
-                            self.bus.write_byte(self.registers.get_hl(), source_value);
+                            self.bus.write_byte(self.registers.get_hl(), source_value)?;
                             self.registers
                                 .set_hl(self.registers.get_hl().wrapping_sub(1));
                         }
                         LoadByteTarget::BC => {
                             t = 8;
-                            self.bus.write_byte(self.registers.get_bc(), source_value);
+                            self.bus.write_byte(self.registers.get_bc(), source_value)?;
                         }
                         LoadByteTarget::DE => {
-                            self.bus.write_byte(self.registers.get_de(), source_value);
+                            t = 8;
+                            self.bus.write_byte(self.registers.get_de(), source_value)?;
                         }
                         LoadByteTarget::RefC => {
                             t = 8;
                             let c_addr = self.registers.c as u16;
                             self.bus
-                                .write_byte(c_addr.wrapping_add(0xFF00), source_value);
+                                .write_byte(c_addr.wrapping_add(0xFF00), source_value)?;
                         }
                         LoadByteTarget::A16 => {
-                            let addr = self.read_next_word();
-                            self.bus.write_byte(addr, source_value);
+                            let addr = self.read_next_word()?;
+                            self.bus.write_byte(addr, source_value)?;
                         }
                         LoadByteTarget::A8 => {
-                            let c_addr = self.bus.read_byte(self.pc + 1) as u16;
+                            let c_addr = self.bus.read_byte(self.pc + 1)? as u16;
                             self.bus
-                                .write_byte(c_addr.wrapping_add(0xFF00), source_value);
+                                .write_byte(c_addr.wrapping_add(0xFF00), source_value)?;
                         }
                     };
                     match (target, source) {
@@ -1455,38 +1548,38 @@ impl CPU {
                 }
                 LoadType::SixteenBitFromAddress(target) => match target {
                     SixteenBitArithmeticTarget::BC => {
-                        let value = self.read_next_word();
+                        let value = self.read_next_word()?;
                         self.registers.set_bc(value);
                         (self.pc.wrapping_add(3), 12)
                     }
                     SixteenBitArithmeticTarget::DE => {
-                        let value = self.read_next_word();
+                        let value = self.read_next_word()?;
                         self.registers.set_de(value);
                         (self.pc.wrapping_add(3), 12)
                     }
                     SixteenBitArithmeticTarget::HL => {
-                        let value = self.read_next_word();
+                        let value = self.read_next_word()?;
                         self.registers.set_hl(value);
                         (self.pc.wrapping_add(3), 12)
                     }
                     SixteenBitArithmeticTarget::SP => {
-                        let value = self.read_next_word();
+                        let value = self.read_next_word()?;
                         self.sp = value;
                         (self.pc.wrapping_add(3), 12)
                     }
                 },
                 LoadType::AddressFromSP => {
-                    let addr = self.read_next_word();
+                    let addr = self.read_next_word()?;
                     let ls_byte = (self.sp & 0xFF) as u8;
                     let ms_byte = (self.sp >> 8) as u8;
 
-                    self.bus.write_byte(addr, ls_byte);
-                    self.bus.write_byte(addr.wrapping_add(1), ms_byte);
+                    self.bus.write_byte(addr, ls_byte)?;
+                    self.bus.write_byte(addr.wrapping_add(1), ms_byte)?;
 
                     (self.pc.wrapping_add(3), 20)
                 }
                 LoadType::HLFromSPN => {
-                    let value = self.addr8(self.sp, true);
+                    let value = self.addr8(self.sp, true)?;
 
                     self.registers.set_hl(value);
 
@@ -1505,11 +1598,11 @@ impl CPU {
                     StackTarget::DE => self.registers.get_de(),
                     StackTarget::HL => self.registers.get_hl(),
                 };
-                self.push(value);
+                self.push(value)?;
                 (self.pc.wrapping_add(1), 16)
             }
             Instruction::POP(target) => {
-                let result = self.pop();
+                let result = self.pop()?;
                 match target {
                     StackTarget::BC => self.registers.set_bc(result),
                     StackTarget::AF => self.registers.set_af(result),
@@ -1526,39 +1619,50 @@ impl CPU {
                     JumpTest::NotZero => !self.registers.f.zero,
                     JumpTest::Zero => self.registers.f.zero,
                 };
-                self.call(jump_condition)
+                self.call(jump_condition)?
             }
             Instruction::RET(test) => {
                 match test {
                     JumpTest::Always => {
                         // `RET` always takes 16 T-states; whereas the other
                         // conditions take 20 T-States if met.
-                        let (addr, _) = self.return_(true);
+                        let (addr, _) = self.return_(true)?;
                         (addr, 16)
                     }
-                    JumpTest::Carry => self.return_(self.registers.f.carry),
-                    JumpTest::NotCarry => self.return_(!self.registers.f.carry),
-                    JumpTest::NotZero => self.return_(!self.registers.f.zero),
-                    JumpTest::Zero => self.return_(self.registers.f.zero),
+                    JumpTest::Carry => self.return_(self.registers.f.carry)?,
+                    JumpTest::NotCarry => self.return_(!self.registers.f.carry)?,
+                    JumpTest::NotZero => self.return_(!self.registers.f.zero)?,
+                    JumpTest::Zero => self.return_(self.registers.f.zero)?,
                 }
             }
             Instruction::RST(n) => {
-                self.push(self.pc);
+                self.push(self.pc)?;
 
                 (n, 16)
             }
             Instruction::NOP => (self.pc.wrapping_add(1), 4),
             Instruction::HALT => {
-                self.is_halted = true;
+                let pending = self.pending_interrupts()? != 0;
+                match self.interrupt {
+                    // EI's delayed enable lands at the end of this step, so
+                    // the interrupt is dispatched next with HALT's own address
+                    // as the return address; HALT runs again after RETI
+                    Interrupt::Transition(true) if pending => return Ok((self.pc, 4)),
+                    Interrupt::Enabled => self.is_halted = true,
+                    _ if pending => self.halt_bug = true,
+                    _ => self.is_halted = true,
+                }
                 (self.pc.wrapping_add(1), 4)
             }
             Instruction::ADDSP => {
-                self.sp = self.addr8(self.sp, true);
+                self.sp = self.addr8(self.sp, true)?;
 
                 (self.pc.wrapping_add(2), 16)
             }
             Instruction::STOP => {
                 self.is_stopped = true;
+                // Entering STOP resets the divider
+                self.bus.write_internal(DIV_ADDR, 0)?;
                 (self.pc.wrapping_add(2), 4)
             }
             Instruction::DAA => {
@@ -1598,9 +1702,10 @@ impl CPU {
             }
             Instruction::RETI => {
                 self.interrupt = Interrupt::Enabled;
-                (self.pop(), 16)
+                (self.pop()?, 16)
             }
-        }
+        };
+        Ok(result)
     }
 
     fn add_half_carry(x: u16, y: u16, is_eight: bool) -> bool {
@@ -1626,43 +1731,44 @@ impl CPU {
         (new_value, is_positive)
     }
 
-    fn push(&mut self, value: u16) {
+    fn push(&mut self, value: u16) -> Result<(), BusError> {
         self.sp = self.sp.wrapping_sub(1);
-        self.bus.write_byte(self.sp, ((value & 0xFF00) >> 8) as u8);
+        self.bus
+            .write_byte(self.sp, ((value & 0xFF00) >> 8) as u8)?;
 
         self.sp = self.sp.wrapping_sub(1);
-        self.bus.write_byte(self.sp, (value & 0xFF) as u8);
+        self.bus.write_byte(self.sp, (value & 0xFF) as u8)
     }
 
-    fn pop(&mut self) -> u16 {
-        let lsb = self.bus.read_byte(self.sp) as u16;
+    fn pop(&mut self) -> Result<u16, BusError> {
+        let lsb = self.bus.read_byte(self.sp)? as u16;
         self.sp = self.sp.wrapping_add(1);
 
-        let msb = self.bus.read_byte(self.sp) as u16;
+        let msb = self.bus.read_byte(self.sp)? as u16;
         self.sp = self.sp.wrapping_add(1);
 
-        (msb << 8) | lsb
+        Ok((msb << 8) | lsb)
     }
 
-    fn call(&mut self, should_jump: bool) -> (u16, u8) {
+    fn call(&mut self, should_jump: bool) -> Result<(u16, u8), BusError> {
         let next_pc = self.pc.wrapping_add(3);
         if should_jump {
-            self.push(next_pc);
-            (self.read_next_word(), 24)
+            self.push(next_pc)?;
+            Ok((self.read_next_word()?, 24))
         } else {
-            (next_pc, 12)
+            Ok((next_pc, 12))
         }
     }
 
-    fn return_(&mut self, should_jump: bool) -> (u16, u8) {
+    fn return_(&mut self, should_jump: bool) -> Result<(u16, u8), BusError> {
         if should_jump {
-            (self.pop(), 20)
+            Ok((self.pop()?, 20))
         } else {
-            (self.pc.wrapping_add(1), 8)
+            Ok((self.pc.wrapping_add(1), 8))
         }
     }
 
-    fn read_next_word(&self) -> u16 {
+    fn read_next_word(&mut self) -> Result<u16, BusError> {
         self.bus.read_word(self.pc.wrapping_add(1))
     }
 
@@ -1674,7 +1780,7 @@ impl CPU {
         // together result in a value bigger than 0xF (16). If the result is larger than 0xF
         // then the addition caused a carry from the lower nibble to the upper nibble
         self.registers.f.half_carry =
-            CPU::add_half_carry(value as u16, self.registers.a as u16, true);
+            Self::add_half_carry(value as u16, self.registers.a as u16, true);
         self.registers.f.carry = did_overflow;
         new_value
     }
@@ -1685,7 +1791,7 @@ impl CPU {
 
         // Does not affect Zero flag
         self.registers.f.subtract = false;
-        self.registers.f.half_carry = CPU::add_half_carry(target, value, false);
+        self.registers.f.half_carry = Self::add_half_carry(target, value, false);
         self.registers.f.carry = did_overflow;
 
         new_value
@@ -1696,7 +1802,7 @@ impl CPU {
         self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = false;
         self.registers.f.half_carry =
-            CPU::add_half_carry(self.registers.a as u16, value as u16, true);
+            Self::add_half_carry(self.registers.a as u16, value as u16, true);
         self.registers.f.carry = did_overflow;
         match self.registers.f.carry {
             true => new_value + 1,
@@ -1710,7 +1816,7 @@ impl CPU {
         self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = true;
         self.registers.f.half_carry =
-            CPU::sub_half_carry(self.registers.a as u16, value as u16, true);
+            Self::sub_half_carry(self.registers.a as u16, value as u16, true);
         self.registers.f.carry = did_overflow;
 
         new_value
@@ -1722,7 +1828,7 @@ impl CPU {
         self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = true;
         self.registers.f.half_carry =
-            CPU::sub_half_carry(self.registers.a as u16, value as u16, true);
+            Self::sub_half_carry(self.registers.a as u16, value as u16, true);
         self.registers.f.carry = value > self.registers.a;
 
         match self.registers.f.carry {
@@ -1771,7 +1877,7 @@ impl CPU {
 
         self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = false;
-        self.registers.f.half_carry = CPU::add_half_carry(value as u16, 1, true);
+        self.registers.f.half_carry = Self::add_half_carry(value as u16, 1, true);
 
         new_value
     }
@@ -1781,7 +1887,7 @@ impl CPU {
 
         self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = true;
-        self.registers.f.half_carry = CPU::sub_half_carry(value as u16, 1, true);
+        self.registers.f.half_carry = Self::sub_half_carry(value as u16, 1, true);
 
         new_value
     }
@@ -1933,27 +2039,27 @@ impl CPU {
         new_value
     }
 
-    fn jump(&self, should_jump: bool) -> (u16, u8) {
+    fn jump(&mut self, should_jump: bool) -> Result<(u16, u8), BusError> {
         if should_jump {
-            (self.read_next_word(), 16)
+            Ok((self.read_next_word()?, 16))
         } else {
-            (self.pc.wrapping_add(3), 12)
+            Ok((self.pc.wrapping_add(3), 12))
         }
     }
 
-    fn addr8(&mut self, target: u16, set_flags: bool) -> u16 {
+    fn addr8(&mut self, target: u16, set_flags: bool) -> Result<u16, BusError> {
         // Identify if n is negative or positive
-        let (n, is_positive) = CPU::sign(self.bus.read_byte(self.pc.wrapping_add(1)));
+        let (n, is_positive) = Self::sign(self.bus.read_byte(self.pc.wrapping_add(1))?);
         // grab the unsigned value from the 'signed' n
         // depending on the operation, add or subtract n from sp
-        match is_positive {
+        let new_value = match is_positive {
             true => {
                 let (new_value, did_overflow) = target.overflowing_add(n as u16);
 
                 if set_flags {
                     self.registers.f.zero = false;
                     self.registers.f.subtract = false;
-                    self.registers.f.half_carry = CPU::add_half_carry(target, n as u16, false);
+                    self.registers.f.half_carry = Self::add_half_carry(target, n as u16, false);
                     self.registers.f.carry = did_overflow;
                 }
 
@@ -1965,14 +2071,14 @@ impl CPU {
                 if set_flags {
                     self.registers.f.zero = false;
                     self.registers.f.subtract = false;
-                    self.registers.f.half_carry = CPU::sub_half_carry(target, n as u16, false);
+                    self.registers.f.half_carry = Self::sub_half_carry(target, n as u16, false);
                     self.registers.f.carry = did_overflow;
                 }
 
                 new_value
             }
-        }
-        // set flags
+        };
+        Ok(new_value)
     }
 }
 
@@ -1986,47 +2092,42 @@ enum Interrupt {
 }
 
 #[cfg(test)]
-use crate::cpu::registers::FlagsRegister;
+use super::memory_interface::FlatRAM;
 #[cfg(test)]
-use std::sync::mpsc::{channel, Receiver};
+use crate::cpu::registers::FlagsRegister;
 
 #[cfg(test)]
-fn create_cpu(a: u8, b: u8, f: FlagsRegister) -> (CPU, Receiver<Request>) {
-    // let test_bus = [8u; 0xFFF].default()
-
-    let (test_sender, test_receiver) = channel::<Request>();
-    (
-        CPU {
-            registers: Registers {
-                a,
-                b,
-                c: 0,
-                d: 0,
-                e: 0,
-                f,
-                h: 0,
-                l: 0,
-            },
-            pc: 0,
-            sp: 0,
-            bus: Bus {
-                request_sender: test_sender,
-            },
-            is_halted: false,
-            is_stopped: false,
-            m: 0,
-            t: 0,
-            interrupt: Interrupt::Enabled,
+pub(super) fn create_cpu(a: u8, b: u8, f: FlagsRegister) -> CPU<FlatRAM> {
+    CPU {
+        registers: Registers {
+            a,
+            b,
+            c: 0,
+            d: 0,
+            e: 0,
+            f,
+            h: 0,
+            l: 0,
         },
-        test_receiver,
-    )
+        pc: 0,
+        sp: 0,
+        bus: FlatRAM::new(),
+        is_halted: false,
+        is_stopped: false,
+        halt_bug: false,
+        m: 0,
+        t: 0,
+        interrupt: Interrupt::Enabled,
+    }
 }
 
 #[test]
 fn test_add() {
-    let (mut test_cpu, _) = create_cpu(15, 1, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(15, 1, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::ADD(ArithmeticTarget::B));
+    test_cpu
+        .execute(Instruction::ADD(ArithmeticTarget::B))
+        .unwrap();
 
     assert_eq!(test_cpu.registers.a, 16);
 
@@ -2038,9 +2139,11 @@ fn test_add() {
 
 #[test]
 fn test_adc() {
-    let (mut test_cpu, _) = create_cpu(255, 2, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(255, 2, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::ADC(ArithmeticTarget::B));
+    test_cpu
+        .execute(Instruction::ADC(ArithmeticTarget::B))
+        .unwrap();
 
     assert_eq!(test_cpu.registers.a, 2);
 
@@ -2052,9 +2155,11 @@ fn test_adc() {
 
 #[test]
 fn test_sub() {
-    let (mut test_cpu, _) = create_cpu(10, 5, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(10, 5, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::SUB(ArithmeticTarget::B));
+    test_cpu
+        .execute(Instruction::SUB(ArithmeticTarget::B))
+        .unwrap();
 
     assert_eq!(test_cpu.registers.a, 5);
 
@@ -2066,9 +2171,11 @@ fn test_sub() {
 
 #[test]
 fn test_sbc() {
-    let (mut test_cpu, _) = create_cpu(0x0, 0x10, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0x0, 0x10, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::SBC(ArithmeticTarget::B));
+    test_cpu
+        .execute(Instruction::SBC(ArithmeticTarget::B))
+        .unwrap();
 
     assert_eq!(test_cpu.registers.a, 239);
     assert_eq!(u8::from(test_cpu.registers.f), 0x50)
@@ -2076,9 +2183,11 @@ fn test_sbc() {
 
 #[test]
 fn test_and() {
-    let (mut test_cpu, _) = create_cpu(0b11110000, 0b00010000, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0b11110000, 0b00010000, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::AND(ArithmeticTarget::B));
+    test_cpu
+        .execute(Instruction::AND(ArithmeticTarget::B))
+        .unwrap();
 
     assert_eq!(test_cpu.registers.a, 0b00010000);
 
@@ -2090,9 +2199,11 @@ fn test_and() {
 
 #[test]
 fn test_or() {
-    let (mut test_cpu, _) = create_cpu(0b11110000, 0b00000001, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0b11110000, 0b00000001, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::OR(ArithmeticTarget::B));
+    test_cpu
+        .execute(Instruction::OR(ArithmeticTarget::B))
+        .unwrap();
 
     assert_eq!(test_cpu.registers.a, 0b11110001);
 
@@ -2104,9 +2215,11 @@ fn test_or() {
 
 #[test]
 fn test_xor() {
-    let (mut test_cpu, _) = create_cpu(0b11110000, 0b11110001, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0b11110000, 0b11110001, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::XOR(ArithmeticTarget::B));
+    test_cpu
+        .execute(Instruction::XOR(ArithmeticTarget::B))
+        .unwrap();
 
     assert_eq!(test_cpu.registers.a, 1);
 
@@ -2118,9 +2231,11 @@ fn test_xor() {
 
 #[test]
 fn test_inc() {
-    let (mut test_cpu, _) = create_cpu(0, 0, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0, 0, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::INC(ArithmeticTarget::A));
+    test_cpu
+        .execute(Instruction::INC(ArithmeticTarget::A))
+        .unwrap();
 
     assert_eq!(test_cpu.registers.a, 1);
 
@@ -2132,9 +2247,11 @@ fn test_inc() {
 
 #[test]
 fn test_dec() {
-    let (mut test_cpu, _) = create_cpu(1, 0, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(1, 0, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::DEC(ArithmeticTarget::A));
+    test_cpu
+        .execute(Instruction::DEC(ArithmeticTarget::A))
+        .unwrap();
 
     assert_eq!(test_cpu.registers.a, 0);
 
@@ -2146,25 +2263,25 @@ fn test_dec() {
 
 #[test]
 fn test_ccf() {
-    let (mut test_cpu, _) = create_cpu(0, 0, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0, 0, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::CCF);
+    test_cpu.execute(Instruction::CCF).unwrap();
 
     assert_eq!(test_cpu.registers.f.zero, false);
     assert_eq!(test_cpu.registers.f.subtract, false);
     assert_eq!(test_cpu.registers.f.half_carry, false);
     assert_eq!(test_cpu.registers.f.carry, true);
 
-    test_cpu.execute(Instruction::CCF);
+    test_cpu.execute(Instruction::CCF).unwrap();
 
     assert_eq!(test_cpu.registers.f.carry, false)
 }
 
 #[test]
 fn test_scf() {
-    let (mut test_cpu, _) = create_cpu(0, 0, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0, 0, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::SCF);
+    test_cpu.execute(Instruction::SCF).unwrap();
 
     assert_eq!(test_cpu.registers.f.zero, false);
     assert_eq!(test_cpu.registers.f.subtract, false);
@@ -2174,7 +2291,7 @@ fn test_scf() {
 
 #[test]
 fn test_rra() {
-    let (mut test_cpu, _) = create_cpu(
+    let mut test_cpu = create_cpu(
         0b00010110,
         0,
         FlagsRegister {
@@ -2185,7 +2302,7 @@ fn test_rra() {
         },
     );
 
-    test_cpu.execute(Instruction::RRA);
+    test_cpu.execute(Instruction::RRA).unwrap();
 
     assert_eq!(test_cpu.registers.a, 0b10001011);
     assert_eq!(test_cpu.registers.f.carry, false)
@@ -2193,7 +2310,7 @@ fn test_rra() {
 
 #[test]
 fn test_rla() {
-    let (mut test_cpu, _) = create_cpu(
+    let mut test_cpu = create_cpu(
         0b000010111,
         0,
         FlagsRegister {
@@ -2204,7 +2321,7 @@ fn test_rla() {
         },
     );
 
-    test_cpu.execute(Instruction::RLA);
+    test_cpu.execute(Instruction::RLA).unwrap();
 
     assert_eq!(test_cpu.registers.a, 0b000101111);
     assert_eq!(test_cpu.registers.f.carry, false)
@@ -2212,7 +2329,7 @@ fn test_rla() {
 
 #[test]
 fn test_rrca() {
-    let (mut test_cpu, _) = create_cpu(
+    let mut test_cpu = create_cpu(
         0b00010111,
         0,
         FlagsRegister {
@@ -2223,7 +2340,7 @@ fn test_rrca() {
         },
     );
 
-    test_cpu.execute(Instruction::RRCA);
+    test_cpu.execute(Instruction::RRCA).unwrap();
 
     assert_eq!(
         test_cpu.registers.a, 0b10001011,
@@ -2237,7 +2354,7 @@ fn test_rrca() {
 
 #[test]
 fn test_rlca() {
-    let (mut test_cpu, _) = create_cpu(
+    let mut test_cpu = create_cpu(
         0b000010111,
         0,
         FlagsRegister {
@@ -2248,7 +2365,7 @@ fn test_rlca() {
         },
     );
 
-    test_cpu.execute(Instruction::RLCA);
+    test_cpu.execute(Instruction::RLCA).unwrap();
 
     assert_eq!(test_cpu.registers.a, 0b000101110);
     assert_eq!(test_cpu.registers.f.carry, false)
@@ -2256,9 +2373,9 @@ fn test_rlca() {
 
 #[test]
 fn test_cpl() {
-    let (mut test_cpu, _) = create_cpu(0b11110000, 0, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0b11110000, 0, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::CPL);
+    test_cpu.execute(Instruction::CPL).unwrap();
 
     assert_eq!(test_cpu.registers.a, 0b00001111, "Testing register A");
 
@@ -2271,9 +2388,11 @@ fn test_cpl() {
 
 #[test]
 fn test_bit() {
-    let (mut test_cpu, _) = create_cpu(0b00001111, 0, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0b00001111, 0, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::BIT(ArithmeticTarget::A, 0));
+    test_cpu
+        .execute(Instruction::BIT(ArithmeticTarget::A, 0))
+        .unwrap();
 
     assert_eq!(
         u8::from(test_cpu.registers.f),
@@ -2281,7 +2400,9 @@ fn test_bit() {
         "Testing reading Bit 0 of A register"
     );
 
-    test_cpu.execute(Instruction::BIT(ArithmeticTarget::A, 7));
+    test_cpu
+        .execute(Instruction::BIT(ArithmeticTarget::A, 7))
+        .unwrap();
 
     assert_eq!(
         u8::from(test_cpu.registers.f),
@@ -2292,9 +2413,11 @@ fn test_bit() {
 
 #[test]
 fn test_res() {
-    let (mut test_cpu, _) = create_cpu(0b00001111, 0, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0b00001111, 0, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::RES(ArithmeticTarget::A, 0));
+    test_cpu
+        .execute(Instruction::RES(ArithmeticTarget::A, 0))
+        .unwrap();
 
     assert_eq!(
         test_cpu.registers.a, 0b00001110,
@@ -2310,9 +2433,11 @@ fn test_res() {
 
 #[test]
 fn test_set() {
-    let (mut test_cpu, _) = create_cpu(0, 0, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0, 0, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::SET(ArithmeticTarget::A, 3));
+    test_cpu
+        .execute(Instruction::SET(ArithmeticTarget::A, 3))
+        .unwrap();
 
     assert_eq!(
         test_cpu.registers.a, 8,
@@ -2328,9 +2453,11 @@ fn test_set() {
 
 #[test]
 fn test_srl() {
-    let (mut test_cpu, _) = create_cpu(0b10001111, 0, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0b10001111, 0, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::SRL(ArithmeticTarget::A));
+    test_cpu
+        .execute(Instruction::SRL(ArithmeticTarget::A))
+        .unwrap();
 
     assert_eq!(
         test_cpu.registers.a, 0b01000111,
@@ -2346,9 +2473,11 @@ fn test_srl() {
 
 #[test]
 fn test_rr() {
-    let (mut test_cpu, _) = create_cpu(0, 0b10000001, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0, 0b10000001, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::RR(ArithmeticTarget::B));
+    test_cpu
+        .execute(Instruction::RR(ArithmeticTarget::B))
+        .unwrap();
 
     assert_eq!(
         test_cpu.registers.b, 0b01000000,
@@ -2364,9 +2493,11 @@ fn test_rr() {
 
 #[test]
 fn test_rl() {
-    let (mut test_cpu, _) = create_cpu(0, 0b10000001, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0, 0b10000001, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::RL(ArithmeticTarget::B));
+    test_cpu
+        .execute(Instruction::RL(ArithmeticTarget::B))
+        .unwrap();
 
     assert_eq!(
         test_cpu.registers.b, 0b00000010,
@@ -2382,9 +2513,11 @@ fn test_rl() {
 
 #[test]
 fn test_rrc() {
-    let (mut test_cpu, _) = create_cpu(0, 0b10000001, FlagsRegister::from(0b00010000));
+    let mut test_cpu = create_cpu(0, 0b10000001, FlagsRegister::from(0b00010000));
 
-    test_cpu.execute(Instruction::RRC(ArithmeticTarget::B));
+    test_cpu
+        .execute(Instruction::RRC(ArithmeticTarget::B))
+        .unwrap();
 
     assert_eq!(
         test_cpu.registers.b, 0b11000000,
@@ -2400,9 +2533,11 @@ fn test_rrc() {
 
 #[test]
 fn test_rlc() {
-    let (mut test_cpu, _) = create_cpu(0, 0b10000001, FlagsRegister::from(0b00010000));
+    let mut test_cpu = create_cpu(0, 0b10000001, FlagsRegister::from(0b00010000));
 
-    test_cpu.execute(Instruction::RLC(ArithmeticTarget::B));
+    test_cpu
+        .execute(Instruction::RLC(ArithmeticTarget::B))
+        .unwrap();
 
     assert_eq!(
         test_cpu.registers.b, 0b00000011,
@@ -2418,9 +2553,11 @@ fn test_rlc() {
 
 #[test]
 fn test_sra() {
-    let (mut test_cpu, _) = create_cpu(0b10100001, 0, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0b10100001, 0, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::SRA(ArithmeticTarget::A));
+    test_cpu
+        .execute(Instruction::SRA(ArithmeticTarget::A))
+        .unwrap();
 
     assert_eq!(
         test_cpu.registers.a, 0b11010000,
@@ -2436,9 +2573,11 @@ fn test_sra() {
 
 #[test]
 fn test_sla() {
-    let (mut test_cpu, _) = create_cpu(0b10100001, 0, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0b10100001, 0, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::SLA(ArithmeticTarget::A));
+    test_cpu
+        .execute(Instruction::SLA(ArithmeticTarget::A))
+        .unwrap();
 
     assert_eq!(
         test_cpu.registers.a, 0b01000010,
@@ -2454,9 +2593,11 @@ fn test_sla() {
 
 #[test]
 fn test_swap() {
-    let (mut test_cpu, _) = create_cpu(0b11110000, 0, FlagsRegister::from(0));
+    let mut test_cpu = create_cpu(0b11110000, 0, FlagsRegister::from(0));
 
-    test_cpu.execute(Instruction::SWAP(ArithmeticTarget::A));
+    test_cpu
+        .execute(Instruction::SWAP(ArithmeticTarget::A))
+        .unwrap();
 
     assert_eq!(
         test_cpu.registers.a, 0b00001111,
@@ -2473,21 +2614,41 @@ fn test_swap() {
 #[test]
 fn test_sign() {
     let test_value = 0xFB;
-    assert_eq!(CPU::sign(test_value), (5, false));
-    assert_eq!(CPU::sign(0b10000000), (128, false));
+    assert_eq!(CPU::<FlatRAM>::sign(test_value), (5, false));
+    assert_eq!(CPU::<FlatRAM>::sign(0b10000000), (128, false));
 }
 
 #[test]
 fn half_carry_add_test() {
     let x = 0b00001000;
     let y = 0b00001000;
-    assert_eq!(CPU::add_half_carry(x as u16, y as u16, true), true)
+    assert_eq!(
+        CPU::<FlatRAM>::add_half_carry(x as u16, y as u16, true),
+        true
+    )
 }
 
 #[test]
 fn half_carry_sub_test() {
     let x: u8 = 0;
     let y: u8 = 0xF;
-    assert_eq!(CPU::sub_half_carry(x as u16, y as u16, true), true)
+    assert_eq!(
+        CPU::<FlatRAM>::sub_half_carry(x as u16, y as u16, true),
+        true
+    )
     // let x_16 = 0
 }
+
+#[test]
+fn test_skip_boot() {
+    let mut test_cpu = create_cpu(0, 0, FlagsRegister::from(0));
+
+    test_cpu.skip_boot();
+
+    assert_eq!(test_cpu.registers.get_af(), 0x01B0);
+    assert_eq!(test_cpu.registers.get_bc(), 0x0013);
+    assert_eq!(test_cpu.registers.get_de(), 0x00D8);
+    assert_eq!(test_cpu.registers.get_hl(), 0x014D);
+    assert_eq!(test_cpu.sp, 0xFFFE);
+    assert_eq!(test_cpu.pc, 0x0100);
+}
@@ -0,0 +1,269 @@
+use super::{
+    cpu::{create_cpu, CPU},
+    instruction::Instruction,
+    memory_interface::{FlatRAM, MemoryInterface},
+    registers::FlagsRegister,
+};
+use crate::interrupt::{IE_ADDR, IF_ADDR};
+
+// Loads `program` at 0x0000 and runs `steps` instructions
+fn run(program: &[u8], steps: usize, setup: impl FnOnce(&mut CPU<FlatRAM>)) -> CPU<FlatRAM> {
+    let mut cpu = create_cpu(0, 0, FlagsRegister::from(0));
+    cpu.bus_mut().load(0x0000, program);
+    setup(&mut cpu);
+    for _ in 0..steps {
+        cpu.step().unwrap();
+    }
+    cpu
+}
+
+#[test]
+fn add_a_hl() {
+    let cpu = run(&[0x86], 1, |cpu| {
+        cpu.registers.a = 0x0F;
+        cpu.registers.set_hl(0xC000);
+        cpu.bus_mut().memory[0xC000] = 0x01;
+    });
+    assert_eq!(cpu.registers.a, 0x10);
+    assert!(cpu.registers.f.half_carry);
+    assert_eq!(cpu.pc, 0x0001);
+    assert_eq!(cpu.bus().t, 8);
+}
+
+#[test]
+fn add_a_d8() {
+    let cpu = run(&[0xC6, 0x80], 1, |cpu| cpu.registers.a = 0x80);
+    assert_eq!(cpu.registers.a, 0x00);
+    assert!(cpu.registers.f.zero);
+    assert!(cpu.registers.f.carry);
+    assert_eq!(cpu.pc, 0x0002);
+}
+
+#[test]
+fn ld_r_d8() {
+    let cpu = run(&[0x06, 0x42, 0x0E, 0x24], 2, |_| {});
+    assert_eq!(cpu.registers.b, 0x42);
+    assert_eq!(cpu.registers.c, 0x24);
+    assert_eq!(cpu.pc, 0x0004);
+    assert_eq!(cpu.bus().t, 16);
+}
+
+#[test]
+fn ld_hl_d8() {
+    let cpu = run(&[0x36, 0x99], 1, |cpu| cpu.registers.set_hl(0xC123));
+    assert_eq!(cpu.bus().memory[0xC123], 0x99);
+    assert_eq!(cpu.pc, 0x0002);
+    assert_eq!(cpu.bus().t, 12);
+}
+
+#[test]
+fn ld_a_hl_increment() {
+    let cpu = run(&[0x2A, 0x2A], 2, |cpu| {
+        cpu.registers.set_hl(0xC000);
+        cpu.bus_mut().load(0xC000, &[0x11, 0x22]);
+    });
+    assert_eq!(cpu.registers.a, 0x22);
+    assert_eq!(cpu.registers.get_hl(), 0xC002);
+}
+
+#[test]
+fn inc_hl() {
+    let cpu = run(&[0x34], 1, |cpu| {
+        cpu.registers.set_hl(0xC000);
+        cpu.bus_mut().memory[0xC000] = 0xFF;
+    });
+    assert_eq!(cpu.bus().memory[0xC000], 0x00);
+    assert!(cpu.registers.f.zero);
+}
+
+#[test]
+fn ld_a16_a_and_back() {
+    // LD (0xC010),A; LD A,0; LD A,(0xC010)
+    let cpu = run(
+        &[0xEA, 0x10, 0xC0, 0x3E, 0x00, 0xFA, 0x10, 0xC0],
+        3,
+        |cpu| cpu.registers.a = 0x5A,
+    );
+    assert_eq!(cpu.bus().memory[0xC010], 0x5A);
+    assert_eq!(cpu.registers.a, 0x5A);
+    assert_eq!(cpu.pc, 0x0008);
+}
+
+#[test]
+fn ld_rr_d16() {
+    let cpu = run(&[0x01, 0x34, 0x12, 0x31, 0xFE, 0xFF], 2, |_| {});
+    assert_eq!(cpu.registers.get_bc(), 0x1234);
+    assert_eq!(cpu.sp, 0xFFFE);
+}
+
+#[test]
+fn ldh_a8_a() {
+    let cpu = run(&[0xE0, 0x80], 1, |cpu| cpu.registers.a = 0x77);
+    assert_eq!(cpu.bus().memory[0xFF80], 0x77);
+    assert_eq!(cpu.pc, 0x0002);
+}
+
+#[test]
+fn call_and_ret() {
+    let mut program = vec![0; 0x20];
+    // CALL 0x0010
+    program[0x00..0x03].copy_from_slice(&[0xCD, 0x10, 0x00]);
+    // RET
+    program[0x10] = 0xC9;
+    let mut cpu = run(&program, 1, |cpu| cpu.sp = 0xFFFE);
+    assert_eq!(cpu.pc, 0x0010);
+    assert_eq!(cpu.sp, 0xFFFC);
+    assert_eq!(cpu.bus_mut().read_word(0xFFFC).unwrap(), 0x0003);
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, 0x0003);
+    assert_eq!(cpu.sp, 0xFFFE);
+    assert_eq!(cpu.bus().t, 24 + 16);
+}
+
+#[test]
+fn bus_accesses_fit_in_instruction_time() {
+    // A bus that clocks the peripherals on every access relies on no
+    // instruction making more accesses than it has M-cycles
+    for prefixed in [false, true] {
+        for byte in 0..=0xFF {
+            if Instruction::from_byte(byte, prefixed).is_none() || (!prefixed && byte == 0xCB) {
+                continue;
+            }
+            let program = if prefixed {
+                vec![0xCB, byte]
+            } else {
+                vec![byte]
+            };
+            // With every flag clear and then set, to take both branches
+            for f in [0x00, 0xF0] {
+                let cpu = run(&program, 1, |cpu| {
+                    cpu.registers.f = FlagsRegister::from(f);
+                    // Large enough for DAA to undo a subtraction with both
+                    // carries set
+                    cpu.registers.a = 0x66;
+                    cpu.sp = 0xFFFE;
+                });
+                let bus = cpu.bus();
+                assert!(
+                    bus.accesses * 4 <= bus.t,
+                    "{}{byte:#04X} made {} accesses in {} t-cycles",
+                    if prefixed { "CB " } else { "" },
+                    bus.accesses,
+                    bus.t
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn interrupt_dispatch() {
+    // Timer and LCD STAT pending: STAT has the higher priority
+    let mut cpu = run(&[0x00], 1, |cpu| {
+        cpu.sp = 0xFFFE;
+        cpu.bus_mut().memory[IE_ADDR as usize] = 0x1F;
+        cpu.bus_mut().memory[IF_ADDR as usize] = 0b0000_0110;
+    });
+    assert_eq!(cpu.pc, 0x0048);
+    assert_eq!(cpu.bus().t, 20);
+    // Only the dispatched interrupt is acknowledged
+    assert_eq!(cpu.bus().memory[IF_ADDR as usize], 0b0000_0100);
+    assert_eq!(cpu.sp, 0xFFFC);
+    assert_eq!(cpu.bus_mut().read_word(0xFFFC).unwrap(), 0x0000);
+
+    // IME is cleared, so the timer interrupt waits and the handler runs
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, 0x0049);
+    assert_eq!(cpu.bus().memory[IF_ADDR as usize], 0b0000_0100);
+}
+
+#[test]
+fn ei_delay() {
+    // DI; EI; NOP; NOP
+    let mut cpu = run(&[0xF3, 0xFB, 0x00, 0x00], 1, |cpu| {
+        cpu.sp = 0xFFFE;
+        cpu.bus_mut().memory[IE_ADDR as usize] = 0x1F;
+    });
+    cpu.bus_mut().memory[IF_ADDR as usize] = 0b0000_0001;
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, 0x0002);
+    // The instruction after EI runs before IME is set
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, 0x0003);
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, 0x0040);
+    assert_eq!(cpu.bus_mut().read_word(0xFFFC).unwrap(), 0x0003);
+}
+
+#[test]
+fn halt_waits_for_pending_interrupt_without_ime() {
+    // DI; HALT; INC A
+    let mut cpu = run(&[0xF3, 0x76, 0x3C], 2, |cpu| {
+        cpu.bus_mut().memory[IE_ADDR as usize] = 0x01;
+    });
+    // Idles in 4 t-cycle steps
+    for _ in 0..3 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(cpu.pc, 0x0002);
+    assert_eq!(cpu.bus().t, 8 + 12);
+
+    // IE & IF wakes it even with IME clear, and nothing is dispatched
+    cpu.bus_mut().memory[IF_ADDR as usize] = 0x01;
+    cpu.step().unwrap();
+    assert_eq!(cpu.registers.a, 1);
+    assert_eq!(cpu.pc, 0x0003);
+}
+
+#[test]
+fn halt_bug() {
+    // DI; HALT; INC A; NOP
+    let mut cpu = run(&[0xF3, 0x76, 0x3C, 0x00], 1, |cpu| {
+        cpu.bus_mut().memory[IE_ADDR as usize] = 0x01;
+    });
+    cpu.bus_mut().memory[IF_ADDR as usize] = 0x01;
+    // With IME clear and an interrupt pending HALT doesn't halt, and the byte
+    // after it is read twice
+    for _ in 0..3 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(cpu.registers.a, 2);
+    assert_eq!(cpu.pc, 0x0003);
+}
+
+#[test]
+fn halt_after_ei() {
+    // EI; HALT; NOP
+    let mut cpu = run(&[0xFB, 0x76, 0x00], 1, |cpu| {
+        cpu.sp = 0xFFFE;
+        cpu.bus_mut().memory[IE_ADDR as usize] = 0x01;
+    });
+    cpu.bus_mut().memory[IF_ADDR as usize] = 0x01;
+    cpu.step().unwrap();
+    // The interrupt is dispatched with HALT as the return address
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, 0x0040);
+    assert_eq!(cpu.bus_mut().read_word(0xFFFC).unwrap(), 0x0001);
+}
+
+#[test]
+fn stop_waits_for_joypad() {
+    // STOP; INC A
+    let mut cpu = run(&[0x10, 0x00, 0x3C], 1, |cpu| {
+        cpu.bus_mut().memory[0xFF00] = 0xCF;
+        cpu.bus_mut().memory[0xFF04] = 0xAB;
+    });
+    // Entering STOP resets DIV
+    assert_eq!(cpu.bus().memory[0xFF04], 0x00);
+    for _ in 0..2 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(cpu.pc, 0x0002);
+    assert_eq!(cpu.registers.a, 0);
+
+    // A joypad line going low wakes it
+    cpu.bus_mut().memory[0xFF00] = 0xC7;
+    cpu.step().unwrap();
+    assert_eq!(cpu.registers.a, 1);
+}
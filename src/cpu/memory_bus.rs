@@ -1,25 +1,36 @@
 use crate::{
+    apu::apu::{APU, APU_END_ADDR, DEFAULT_SAMPLE_RATE, NR10_ADDR},
+    audio::recorder::Recorder,
+    cartridge::cartridge::Cartridge,
+    dma::{DMA_ADDR, OAMDMA},
     gpu::gpu::GPU,
-    request_response::{Request, RequestType, Response},
+    interrupt::{InterruptFlag, IF_ADDR},
+    joypad::{Joypad, JoypadEvent, JOYP_ADDR},
+    request_response::{Request, RequestInfo, RequestType, Response},
+    timer::{Timer, DIV_ADDR, TAC_ADDR},
 };
 use std::{
     fs,
-    sync::mpsc::{Receiver, Sender},
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, SyncSender},
+    time::{Duration, Instant},
 };
 
 /*
 
 Memory map
 
+0x0000 - 0x00FF: Boot ROM, overlaid on the cartridge until 0xFF50 is written
 0x0000 - 0x3FFF: Rom Bank #0
 0x4000 - 0x7FFF: swithcable ROM bank
 0x8000 - 0x9FFF: Video RAM
 0xA000 - 0xBFFF: Switchable RAM bank
 0xC000 - 0xDFFF: Internal RAM
-0xE000 - 0xFDFF: Echo of Internal RAM
-0xFE00 - 0xFE9F: Sprite Attrib Mem (OAM)
-0xFEA0 - 0xFEFF: Empty but unusable for I/O (Ignore)
-0xFF00 - 0xFF7F: I/O Ports
+0xE000 - 0xFDFF: Echo of Internal RAM (0xC000 - 0xDDFF)
+0xFE00 - 0xFE9F: Sprite Attrib Mem (OAM), filled by DMA from 0xFF46
+0xFEA0 - 0xFEFF: Unusable, reads 0x00 (0xFF during OAM DMA), writes ignored
+0xFF00 - 0xFF7F: I/O Ports, unused bits and registers read as 1
+0xFF10 - 0xFF3F: Sound registers and wave RAM, owned by the APU
 0xFF80 - 0xFFFE: Internal / High RAM
 0xFFFF: Interrupt Enable Register
 
@@ -30,82 +41,630 @@ pub struct MemoryBus {
     memory: [u8; 0x10000],
     request_receiver: Receiver<Request>,
     rom_name: String,
+    // Mapped over 0x0000 - 0x00FF until the boot ROM writes to 0xFF50
+    boot_rom: Option<Vec<u8>>,
+    cartridge: Cartridge,
+    timer: Timer,
+    joypad: Joypad,
+    apu: APU,
+    // Batches of APU samples go here; without a receiver they are dropped
+    audio_sender: Option<SyncSender<Vec<[f32; 2]>>>,
+    // Recordings are resampled to the rate of the audio output
+    output_rate: u32,
+    recorder: Option<Recorder>,
+    dma: Option<OAMDMA>,
+    // Time of the latest battery RAM or RTC write that hasn't been saved yet
+    last_unsaved_write: Option<Instant>,
 }
 
+pub const ROM_DIR: &str = "./roms";
+
+const BOOT_ROM_DISABLE_ADDR: u16 = 0xFF50;
+const BOOT_ROM_NAME: &str = "gb_bios.bin";
+
+// IO register values the DMG boot ROM leaves behind when it hands over to the
+// cartridge at 0x0100. DIV is set through `Timer::skip_boot`, as writing it
+// resets the counter. NR52 comes first since the APU ignores writes while
+// off, and the NRx4 trigger bits are left clear so no channel starts playing
+// (they read back as 1 either way)
+const POST_BOOT_IO: [(u16, u8); 39] = [
+    (0xFF00, 0xCF), // P1
+    (0xFF01, 0x00), // SB
+    (0xFF02, 0x7E), // SC
+    (0xFF05, 0x00), // TIMA
+    (0xFF06, 0x00), // TMA
+    (0xFF07, 0xF8), // TAC
+    (0xFF0F, 0xE1), // IF
+    (0xFF26, 0xF1), // NR52
+    (0xFF10, 0x80), // NR10
+    (0xFF11, 0xBF), // NR11
+    (0xFF12, 0xF3), // NR12
+    (0xFF13, 0xFF), // NR13
+    (0xFF14, 0x3F), // NR14
+    (0xFF16, 0x3F), // NR21
+    (0xFF17, 0x00), // NR22
+    (0xFF18, 0xFF), // NR23
+    (0xFF19, 0x3F), // NR24
+    (0xFF1A, 0x7F), // NR30
+    (0xFF1B, 0xFF), // NR31
+    (0xFF1C, 0x9F), // NR32
+    (0xFF1D, 0xFF), // NR33
+    (0xFF1E, 0x3F), // NR34
+    (0xFF20, 0xFF), // NR41
+    (0xFF21, 0x00), // NR42
+    (0xFF22, 0x00), // NR43
+    (0xFF23, 0x3F), // NR44
+    (0xFF24, 0x77), // NR50
+    (0xFF25, 0xF3), // NR51
+    (0xFF40, 0x91), // LCDC
+    (0xFF41, 0x85), // STAT
+    (0xFF42, 0x00), // SCY
+    (0xFF43, 0x00), // SCX
+    (0xFF45, 0x00), // LYC
+    (0xFF46, 0xFF), // DMA
+    (0xFF47, 0xFC), // BGP
+    (0xFF48, 0xFF), // OBP0
+    (0xFF49, 0xFF), // OBP1
+    (0xFF4A, 0x00), // WY
+    (0xFF4B, 0x00), // WX
+];
+
+// Battery RAM is flushed once writes have settled for this long
+const SAVE_DEBOUNCE: Duration = Duration::from_secs(1);
+
+// Samples are handed to `audio_sender` in batches of this size
+const AUDIO_BATCH: usize = 4096;
+
 impl MemoryBus {
-    pub fn new(request_receiver: Receiver<Request>, rom_name: String) -> MemoryBus {
-        let data = MemoryBus::read_data("gb_bios.bin");
+    // Without a boot ROM the bus starts in the state the boot ROM would have
+    // left it in; the CPU has to be started with `CPU::skip_boot` to match
+    pub fn new(
+        request_receiver: Receiver<Request>,
+        rom_name: String,
+        boot_rom: Option<Vec<u8>>,
+        joypad_receiver: Receiver<JoypadEvent>,
+        audio_sender: Option<SyncSender<Vec<[f32; 2]>>>,
+        output_rate: u32,
+    ) -> MemoryBus {
+        let cartridge = MemoryBus::load_cartridge(&rom_name);
+        MemoryBus::with_cartridge(
+            request_receiver,
+            rom_name,
+            cartridge,
+            boot_rom,
+            joypad_receiver,
+            audio_sender,
+            output_rate,
+        )
+    }
+
+    pub fn with_cartridge(
+        request_receiver: Receiver<Request>,
+        rom_name: String,
+        cartridge: Cartridge,
+        boot_rom: Option<Vec<u8>>,
+        joypad_receiver: Receiver<JoypadEvent>,
+        audio_sender: Option<SyncSender<Vec<[f32; 2]>>>,
+        output_rate: u32,
+    ) -> MemoryBus {
+        let skip_boot = boot_rom.is_none();
         let mut memory_bus = MemoryBus {
             memory: [0; 0x10000],
             request_receiver,
             rom_name,
+            boot_rom,
+            cartridge,
+            timer: Timer::new(),
+            joypad: Joypad::new(joypad_receiver),
+            apu: APU::new(DEFAULT_SAMPLE_RATE),
+            audio_sender,
+            output_rate,
+            recorder: None,
+            dma: None,
+            last_unsaved_write: None,
         };
-        memory_bus.load_data(data);
+        if skip_boot {
+            for (addr, value) in POST_BOOT_IO {
+                memory_bus.write(addr, value);
+            }
+            // The DMA register is left at 0xFF, but no transfer is running
+            memory_bus.dma = None;
+            memory_bus.timer.skip_boot();
+            memory_bus.write(BOOT_ROM_DISABLE_ADDR, 0xFF);
+        }
         memory_bus
     }
 
+    pub fn read_boot_rom() -> Result<Vec<u8>, String> {
+        let path = Path::new(ROM_DIR).join(BOOT_ROM_NAME);
+        fs::read(&path).map_err(|err| format!("{}: {err:}", path.display()))
+    }
+
     pub fn step(&mut self) {
         match self.request_receiver.recv() {
             Ok(request) => {
-                let request_info = request.request_info;
-                match request_info.request_type {
-                    RequestType::Read => self.send_read(
-                        request_info.addr,
-                        request_info.request_len,
-                        request.responder,
-                    ),
-                    RequestType::Write(data) => {
-                        self.send_write(request_info.addr, data, request.responder)
-                    }
-                    RequestType::LoadROM => {
-                        let data = MemoryBus::read_data(&self.rom_name);
-                        self.load_data(data);
-                        request.responder.send(Response::Ok204).unwrap();
-                    }
-                }
+                let response = self.handle(request.request_info);
+                request.responder.send(response).unwrap();
             }
             Err(err) => panic!("{err:}"),
         };
+        self.save_if_settled();
+    }
+
+    // Serves the requests already queued without blocking, for when the bus
+    // is driven from the emulation thread rather than running its own
+    pub fn serve_pending(&mut self) {
+        while let Ok(request) = self.request_receiver.try_recv() {
+            let response = self.handle(request.request_info);
+            // The requester may have given up waiting
+            let _ = request.responder.send(response);
+        }
+        self.save_if_settled();
     }
 
-    // fn resolve_request(&mut self, request: Request) {
+    fn handle(&mut self, request_info: RequestInfo) -> Response {
+        match request_info.request_type {
+            RequestType::Read => self.serve_read(request_info.addr, request_info.request_len),
+            RequestType::Write(data) => {
+                self.serve_write(request_info.addr, request_info.request_len, data)
+            }
+            RequestType::Interrupt(flag) => {
+                self.request_interrupt(flag);
+                Response::Ok204
+            }
+            RequestType::Save => {
+                self.save();
+                Response::Ok204
+            }
+            RequestType::Tick(t) => {
+                self.tick(t);
+                Response::Ok204
+            }
+            RequestType::StartRecording(path, stems) => match self.start_recording(&path, stems) {
+                Ok(()) => Response::Ok204,
+                Err(err) => Response::RequestError(err),
+            },
+            RequestType::StopRecording => {
+                self.stop_recording();
+                Response::Ok204
+            }
+        }
+    }
 
-    // }
+    fn save_if_settled(&mut self) {
+        if let Some(last_unsaved_write) = self.last_unsaved_write {
+            if last_unsaved_write.elapsed() >= SAVE_DEBOUNCE {
+                self.save();
+            }
+        }
+    }
 
-    fn send_read(&self, addr: u16, request_len: u8, responder: Sender<Response>) {
-        let addr = addr as usize;
-        let request_len = request_len as usize;
-        let data = self.memory[addr..addr + request_len].to_vec();
+    // Multi-byte requests wrap at 0xFFFF like the CPU's 16-bit addressing
+    fn serve_read(&self, addr: u16, request_len: u8) -> Response {
+        if request_len == 0 {
+            return Response::RequestError(format!("Read of 0 bytes at {addr:#06X}"));
+        }
+        let data = (0..request_len as u16)
+            .map(|i| self.read(addr.wrapping_add(i)))
+            .collect();
 
-        responder.send(Response::Ok200(data)).unwrap();
+        Response::Ok200(data)
     }
-    fn send_write(&mut self, addr: u16, data: Vec<u8>, responder: Sender<Response>) {
-        let mut addr = addr as usize;
+
+    fn serve_write(&mut self, addr: u16, request_len: u8, data: Vec<u8>) -> Response {
+        if data.is_empty() || data.len() != request_len as usize {
+            return Response::RequestError(format!(
+                "Write of {} bytes at {addr:#06X} with request_len {request_len}",
+                data.len()
+            ));
+        }
+        let mut addr = addr;
         for x in data {
-            self.memory[addr] = x;
-            addr += 1;
+            self.write(addr, x);
+            addr = addr.wrapping_add(1);
+        }
+
+        Response::Ok204
+    }
+
+    // Reads as seen by the CPU and GPU, which conflict with OAM DMA
+    pub fn read(&self, addr: u16) -> u8 {
+        match &self.dma {
+            Some(dma) if dma.blocks(addr) => match addr {
+                0xFE00..=0xFEFF => 0xFF,
+                _ => dma.byte,
+            },
+            _ => self.read_direct(addr),
+        }
+    }
+
+    fn read_direct(&self, addr: u16) -> u8 {
+        match (addr, &self.boot_rom) {
+            (0x0000..=0x00FF, Some(boot_rom)) => {
+                boot_rom.get(addr as usize).copied().unwrap_or(0xFF)
+            }
+            (0x0000..=0x7FFF, _) => self.cartridge.read_rom(addr),
+            (0xA000..=0xBFFF, _) => self.cartridge.read_ram(addr),
+            (JOYP_ADDR, _) => self.joypad.read(),
+            (DIV_ADDR..=TAC_ADDR, _) => self.timer.read(addr),
+            (NR10_ADDR..=APU_END_ADDR, _) => self.apu.read(addr),
+            (0xE000..=0xFDFF, _) => self.memory[addr as usize - 0x2000],
+            (0xFEA0..=0xFEFF, _) => 0x00,
+            (0xFF00..=0xFF7F, _) => self.memory[addr as usize] | MemoryBus::io_read_mask(addr),
+            _ => self.memory[addr as usize],
+        }
+    }
+
+    // Bits of the IO registers kept in `memory` that aren't wired up and read
+    // as 1. Registers missing on the DMG read as 0xFF
+    fn io_read_mask(addr: u16) -> u8 {
+        match addr {
+            0xFF01 => 0x00, // SB
+            0xFF02 => 0x7E, // SC
+            IF_ADDR => 0xE0,
+            0xFF40 => 0x00, // LCDC
+            0xFF41 => 0x80, // STAT
+            0xFF42..=0xFF4B => 0x00,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        if self.dma.as_ref().is_some_and(|dma| dma.blocks(addr)) {
+            return;
+        }
+        match addr {
+            // ROM writes are latched by the MBC
+            0x0000..=0x7FFF => {
+                self.cartridge.write_rom(addr, value);
+                // Latching the clock changes the RTC trailer of the save
+                if addr >= 0x6000 && self.cartridge.has_rtc() {
+                    self.mark_unsaved();
+                }
+            }
+            0xA000..=0xBFFF => {
+                self.cartridge.write_ram(addr, value);
+                self.mark_unsaved();
+            }
+            JOYP_ADDR => {
+                if self.joypad.write(value) {
+                    self.request_interrupt(InterruptFlag::Joypad);
+                }
+            }
+            DIV_ADDR..=TAC_ADDR => {
+                // Resetting DIV can be a falling edge for the frame sequencer
+                let div_apu_bit = self.timer.div_apu_bit();
+                self.timer.write(addr, value);
+                if div_apu_bit && !self.timer.div_apu_bit() {
+                    self.apu.step_frame_sequencer();
+                }
+            }
+            NR10_ADDR..=APU_END_ADDR => self.apu.write(addr, value),
+            0xE000..=0xFDFF => self.memory[addr as usize - 0x2000] = value,
+            0xFEA0..=0xFEFF => {}
+            DMA_ADDR => {
+                // Writing again restarts the transfer
+                self.dma = Some(OAMDMA::new(value));
+                self.memory[addr as usize] = value;
+            }
+            BOOT_ROM_DISABLE_ADDR => {
+                // Any non-zero write unmaps the boot ROM until the next reset
+                if value != 0 {
+                    self.boot_rom = None;
+                }
+                self.memory[addr as usize] = value;
+            }
+            _ => self.memory[addr as usize] = value,
+        }
+    }
+
+    fn load_cartridge(rom_name: &str) -> Cartridge {
+        let data = MemoryBus::read_data(rom_name);
+        let mut cartridge = Cartridge::new(data);
+        match cartridge.header() {
+            Some(header) => {
+                for warning in header.validate(cartridge.rom()) {
+                    println!("Warning: {rom_name}: {warning}");
+                }
+            }
+            None => println!("Warning: {rom_name} has no cartridge header"),
         }
+        if cartridge.has_battery() {
+            let save_path = MemoryBus::save_path(rom_name);
+            if let Ok(save) = fs::read(save_path) {
+                cartridge.load_save_data(&save);
+            }
+        }
+        cartridge
+    }
 
-        responder.send(Response::Ok204).unwrap();
+    fn save_path(rom_name: &str) -> PathBuf {
+        Path::new(ROM_DIR).join(rom_name).with_extension("sav")
+    }
+
+    // Every write restarts the wait for writes to settle
+    fn mark_unsaved(&mut self) {
+        if self.cartridge.has_battery() {
+            self.last_unsaved_write = Some(Instant::now());
+        }
+    }
+
+    fn save(&mut self) {
+        self.last_unsaved_write = None;
+        if !self.cartridge.has_battery() {
+            return;
+        }
+        let save_path = MemoryBus::save_path(&self.rom_name);
+        if let Err(err) = fs::write(&save_path, self.cartridge.save_data()) {
+            println!("Warning: could not write {}: {err:}", save_path.display());
+        }
+    }
+
+    pub fn tick(&mut self, t: u8) {
+        for _ in 0..t / 4 {
+            let div_apu_bit = self.timer.div_apu_bit();
+            if self.timer.tick(4) {
+                self.request_interrupt(InterruptFlag::Timer);
+            }
+            if div_apu_bit && !self.timer.div_apu_bit() {
+                self.apu.step_frame_sequencer();
+            }
+            self.apu.tick(4);
+            self.step_dma();
+        }
+        if self.apu.buffered_samples() >= AUDIO_BATCH {
+            self.flush_audio();
+        }
+        if self.joypad.update() {
+            self.request_interrupt(InterruptFlag::Joypad);
+        }
+    }
+
+    fn step_dma(&mut self) {
+        let transfer = match &mut self.dma {
+            Some(dma) => dma.step(),
+            None => return,
+        };
+        if let Some((source, destination)) = transfer {
+            let byte = self.read_direct(source);
+            self.memory[destination as usize] = byte;
+            let dma = self.dma.as_mut().unwrap();
+            dma.byte = byte;
+            if dma.is_done() {
+                self.dma = None;
+            }
+        }
+    }
+
+    // Hands the samples produced so far to the recorder and audio backend
+    fn flush_audio(&mut self) {
+        let samples = self.apu.take_samples();
+        let stems = self.apu.take_stems();
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(err) = recorder.write(&samples, &stems) {
+                println!("Warning: stopped recording: {err}");
+                self.recorder = None;
+                self.apu.record_stems(false);
+            }
+        }
+        if let Some(audio_sender) = &self.audio_sender {
+            // A closed audio backend just means no sound
+            if audio_sender.send(samples).is_err() {
+                self.audio_sender = None;
+            }
+        }
+    }
+
+    // Recordings start and stop between instructions, so the samples they
+    // hold only depend on the emulated clock
+    pub fn start_recording(&mut self, path: &Path, stems: bool) -> Result<(), String> {
+        self.stop_recording();
+        self.recorder = Some(Recorder::create(
+            path,
+            stems,
+            self.apu.sample_rate(),
+            self.output_rate,
+        )?);
+        self.apu.record_stems(stems);
+        Ok(())
+    }
+
+    fn stop_recording(&mut self) {
+        self.flush_audio();
+        self.recorder = None;
+        self.apu.record_stems(false);
+    }
+
+    fn request_interrupt(&mut self, flag: InterruptFlag) {
+        self.memory[IF_ADDR as usize] |= flag.bit();
     }
 
     fn read_data(filename: &str) -> Vec<u8> {
-        let file = fs::read(format!("./roms/{filename:}"));
+        let file = fs::read(Path::new(ROM_DIR).join(filename));
         match file {
             Ok(data) => data,
             Err(err) => panic!("{err:}"),
         }
     }
+}
 
-    fn load_data(&mut self, data: Vec<u8>) {
-        let mut i = 0;
-        while i < data.len() {
-            self.memory[i] = data[i];
-            i += 1;
-        }
+#[cfg(test)]
+fn create_memory_bus() -> MemoryBus {
+    let (_, request_receiver) = std::sync::mpsc::channel();
+    create_memory_bus_with_receiver(request_receiver, vec![0; 0x8000])
+}
+
+// A bus over `rom` without a boot ROM, audio or joypad input
+#[cfg(test)]
+pub(crate) fn create_memory_bus_with_receiver(
+    request_receiver: Receiver<Request>,
+    rom: Vec<u8>,
+) -> MemoryBus {
+    use crate::audio::output::DEFAULT_OUTPUT_RATE;
+
+    let (_, joypad_receiver) = std::sync::mpsc::channel();
+    MemoryBus::with_cartridge(
+        request_receiver,
+        String::from("test.gb"),
+        Cartridge::new(rom),
+        None,
+        joypad_receiver,
+        None,
+        DEFAULT_OUTPUT_RATE,
+    )
+}
+
+#[test]
+fn test_oam_dma() {
+    let mut bus = create_memory_bus();
+    for i in 0..0xA0 {
+        bus.write(0xC100 + i, i as u8);
+    }
+    bus.write(DMA_ADDR, 0xC1);
+    // Start-up m-cycle and the first byte
+    bus.tick(8);
+    assert_eq!(bus.read(0xFE00), 0xFF);
+    assert_eq!(bus.read(0xC000), 0x00);
+    assert_eq!(bus.read(0x0150), 0x00);
+    // HRAM is still reachable, WRAM writes are dropped
+    bus.write(0xFF80, 0x42);
+    assert_eq!(bus.read(0xFF80), 0x42);
+    bus.write(0xC100, 0x42);
+
+    for _ in 0..159 {
+        bus.tick(4);
     }
+    assert!(bus.dma.is_none());
+    for i in 0..0xA0 {
+        assert_eq!(bus.read(0xFE00 + i), i as u8);
+    }
+}
+
+// Sends a request through the channel and serves it
+#[cfg(test)]
+fn serve_request(addr: u16, request_len: u8, request_type: RequestType) -> Response {
+    use crate::request_response::RequestInfo;
+    use std::sync::mpsc::channel;
+
+    let (request_sender, request_receiver) = channel();
+    let mut bus = create_memory_bus_with_receiver(request_receiver, vec![0; 0x8000]);
+    bus.write(0xFFFF, 0x12);
+    let (responder, response_receiver) = channel();
+    let request_info = RequestInfo {
+        addr,
+        request_len,
+        request_type,
+    };
+    request_sender
+        .send(Request {
+            request_info,
+            responder,
+        })
+        .unwrap();
+    bus.step();
+    response_receiver.recv().unwrap()
 }
 
+#[test]
+fn test_requests_wrap_at_end_of_address_space() {
+    match serve_request(0xFFFF, 2, RequestType::Read) {
+        Response::Ok200(data) => assert_eq!(data, vec![0x12, 0x00]),
+        _ => panic!("Expected data"),
+    }
+    assert!(matches!(
+        serve_request(0xFFFF, 2, RequestType::Write(vec![0x01, 0x02])),
+        Response::Ok204
+    ));
+}
+
+#[test]
+fn test_invalid_requests() {
+    assert!(matches!(
+        serve_request(0xC000, 0, RequestType::Read),
+        Response::RequestError(_)
+    ));
+    assert!(matches!(
+        serve_request(0xC000, 2, RequestType::Write(vec![0x01])),
+        Response::RequestError(_)
+    ));
+}
+
+#[test]
+fn test_echo_ram() {
+    let mut bus = create_memory_bus();
+    bus.write(0xC123, 0x12);
+    assert_eq!(bus.read(0xE123), 0x12);
+    bus.write(0xFDFF, 0x34);
+    assert_eq!(bus.read(0xDDFF), 0x34);
+}
+
+#[test]
+fn test_unusable_and_open_bus_reads() {
+    let mut bus = create_memory_bus();
+    bus.write(0xFEA0, 0x12);
+    assert_eq!(bus.read(0xFEA0), 0x00);
+    // Unmapped registers and bits
+    bus.write(0xFF03, 0x00);
+    assert_eq!(bus.read(0xFF03), 0xFF);
+    bus.write(IF_ADDR, 0x01);
+    assert_eq!(bus.read(IF_ADDR), 0xE1);
+    bus.write(0xFF02, 0x81);
+    assert_eq!(bus.read(0xFF02), 0xFF);
+    bus.write(0xFF42, 0x00);
+    assert_eq!(bus.read(0xFF42), 0x00);
+}
+
+#[test]
+fn test_rom_writes_go_to_mbc() {
+    let mut bus = create_memory_bus();
+    bus.write(0x0100, 0x12);
+    assert_eq!(bus.read(0x0100), 0x00);
+}
+
+#[test]
+fn test_oam_dma_echo_source() {
+    let mut bus = create_memory_bus();
+    bus.write(0xDE05, 0x77);
+    bus.write(DMA_ADDR, 0xFE);
+    for _ in 0..161 {
+        bus.tick(4);
+    }
+    assert_eq!(bus.read(0xFE05), 0x77);
+}
+
+#[test]
+fn test_save_waits_for_writes_to_settle() {
+    // MBC3+TIMER+RAM+BATTERY
+    let mut rom = vec![0; 0x8000];
+    rom[0x0147] = 0x10;
+    rom[0x0149] = 0x02;
+    let (_, request_receiver) = std::sync::mpsc::channel();
+    let mut bus = create_memory_bus_with_receiver(request_receiver, rom);
+    let save_path = std::env::temp_dir().join(format!("gb_emulator_{}.sav", std::process::id()));
+    let _ = fs::remove_file(&save_path);
+    bus.rom_name = save_path.with_extension("gb").display().to_string();
+    bus.write(0x0000, 0x0A);
+
+    // Writes 900 ms apart keep pushing the save back, well past
+    // SAVE_DEBOUNCE after the first one. Latching the RTC counts as a write
+    for i in 0..4 {
+        match i {
+            2 => bus.write(0x6000, 0x01),
+            _ => bus.write(0xA000, i),
+        }
+        bus.last_unsaved_write = bus
+            .last_unsaved_write
+            .map(|write| write - Duration::from_millis(900));
+        bus.save_if_settled();
+        assert!(!save_path.exists(), "saved while still writing");
+    }
+
+    bus.last_unsaved_write = bus.last_unsaved_write.map(|write| write - SAVE_DEBOUNCE);
+    bus.save_if_settled();
+    assert!(save_path.exists());
+    assert!(bus.last_unsaved_write.is_none());
+    fs::remove_file(&save_path).unwrap();
+}
 // #[cfg(test)]
 // #[test]
 // fn write_read_byte_test() {
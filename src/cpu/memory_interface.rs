@@ -0,0 +1,266 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::memory_bus::MemoryBus;
+use crate::{
+    gpu::gpu::GPU,
+    interrupt::{InterruptFlag, IF_ADDR},
+    request_response::{Bus, BusError},
+};
+
+/*
+
+Memory as seen by the CPU and PPU
+
+Implemented by:
+Bus: requests over a channel to the memory thread
+DirectBus: calls into a `MemoryBus` on the same thread, used by `GameBoy`
+FlatRAM: 64 KiB of plain RAM with no peripherals, for tests
+
+Words are little-endian and wrap at 0xFFFF. Each byte the CPU reads or writes
+takes one M-cycle; an instruction's remaining M-cycles are internal.
+
+*/
+
+pub trait MemoryInterface {
+    fn read_byte(&mut self, addr: u16) -> Result<u8, BusError>;
+
+    fn write_byte(&mut self, addr: u16, value: u8) -> Result<(), BusError>;
+
+    // Accesses the CPU makes outside of its bus cycles: the interrupt logic
+    // checking IE/IF, HALT and STOP polling for a wake-up and STOP resetting
+    // DIV. They take no time of their own
+    fn read_internal(&mut self, addr: u16) -> Result<u8, BusError> {
+        self.read_byte(addr)
+    }
+
+    fn write_internal(&mut self, addr: u16, value: u8) -> Result<(), BusError> {
+        self.write_byte(addr, value)
+    }
+
+    fn read_word(&mut self, addr: u16) -> Result<u16, BusError> {
+        let low = self.read_byte(addr)? as u16;
+        let high = self.read_byte(addr.wrapping_add(1))? as u16;
+        Ok((high << 8) | low)
+    }
+
+    fn write_word(&mut self, addr: u16, value: u16) -> Result<(), BusError> {
+        self.write_byte(addr, (value & 0xFF) as u8)?;
+        self.write_byte(addr.wrapping_add(1), (value >> 8) as u8)
+    }
+
+    // Reads `len` bytes starting at `addr`, e.g. all of OAM at once
+    fn read_bytes(&mut self, addr: u16, len: u8) -> Result<Vec<u8>, BusError> {
+        (0..len as u16)
+            .map(|i| self.read_byte(addr.wrapping_add(i)))
+            .collect()
+    }
+
+    // Sets `flag` in IF
+    fn request_interrupt(&mut self, flag: InterruptFlag) -> Result<(), BusError> {
+        let requested = self.read_byte(IF_ADDR)?;
+        self.write_byte(IF_ADDR, requested | flag.bit())
+    }
+
+    // Called after each CPU step with the t-cycles it took, to advance the
+    // peripherals clocked alongside the CPU by whatever the step's accesses
+    // haven't already
+    fn tick(&mut self, t: u8) -> Result<(), BusError>;
+}
+
+impl MemoryInterface for Bus {
+    fn read_byte(&mut self, addr: u16) -> Result<u8, BusError> {
+        self.try_read_byte(addr)
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) -> Result<(), BusError> {
+        self.try_write_byte(addr, value)
+    }
+
+    // One request rather than two
+    fn read_word(&mut self, addr: u16) -> Result<u16, BusError> {
+        self.try_read_word(addr)
+    }
+
+    fn write_word(&mut self, addr: u16, value: u16) -> Result<(), BusError> {
+        self.try_write(addr, vec![(value & 0xFF) as u8, (value >> 8) as u8])
+    }
+
+    fn read_bytes(&mut self, addr: u16, len: u8) -> Result<Vec<u8>, BusError> {
+        self.try_read(addr, len)
+    }
+
+    fn request_interrupt(&mut self, flag: InterruptFlag) -> Result<(), BusError> {
+        self.try_request_interrupt(flag)
+    }
+
+    fn tick(&mut self, t: u8) -> Result<(), BusError> {
+        self.try_tick(t)
+    }
+}
+
+pub struct DirectBus {
+    memory: Rc<RefCell<MemoryBus>>,
+    // Set on the CPU's bus: the PPU, stepped along with the timer, APU and DMA
+    // before every access
+    ppu: Option<Box<GPU<DirectBus>>>,
+    // T-cycles the PPU is behind the memory
+    ppu_lag: i32,
+    // T-cycles ticked by accesses so far in the current CPU step
+    step_t: u8,
+}
+
+impl DirectBus {
+    pub fn new(memory: Rc<RefCell<MemoryBus>>) -> DirectBus {
+        DirectBus {
+            memory,
+            ppu: None,
+            ppu_lag: 0,
+            step_t: 0,
+        }
+    }
+
+    // Makes this the CPU's bus, which clocks the peripherals and `ppu` one
+    // M-cycle ahead of each access
+    pub fn with_ppu(mut self, ppu: GPU<DirectBus>) -> DirectBus {
+        self.ppu = Some(Box::new(ppu));
+        self
+    }
+
+    // A bus over a blank 32 KiB cartridge without a boot ROM, audio or
+    // joypad input, after `writes` to its memory
+    #[cfg(test)]
+    pub(crate) fn with_writes(writes: &[(u16, u8)]) -> DirectBus {
+        use super::memory_bus::create_memory_bus_with_receiver;
+
+        let (_, request_receiver) = std::sync::mpsc::channel();
+        let mut memory = create_memory_bus_with_receiver(request_receiver, vec![0; 0x8000]);
+        for &(addr, value) in writes {
+            memory.write(addr, value);
+        }
+        DirectBus::new(Rc::new(RefCell::new(memory)))
+    }
+
+    pub fn serve_pending(&self) {
+        self.memory.borrow_mut().serve_pending();
+    }
+
+    fn advance(&mut self, t: u8) -> Result<(), BusError> {
+        self.memory.borrow_mut().tick(t);
+        if let Some(ppu) = &mut self.ppu {
+            self.ppu_lag += t as i32;
+            while self.ppu_lag > 0 {
+                self.ppu_lag -= ppu.step()? as i32;
+            }
+        }
+        Ok(())
+    }
+
+    // The M-cycle of a CPU access, which only the CPU's bus keeps time for
+    fn cycle(&mut self) -> Result<(), BusError> {
+        if self.ppu.is_none() {
+            return Ok(());
+        }
+        self.step_t += 4;
+        self.advance(4)
+    }
+}
+
+// Clones share the memory but not the PPU, for the PPU's own accesses
+impl Clone for DirectBus {
+    fn clone(&self) -> Self {
+        DirectBus::new(self.memory.clone())
+    }
+}
+
+impl MemoryInterface for DirectBus {
+    fn read_byte(&mut self, addr: u16) -> Result<u8, BusError> {
+        self.cycle()?;
+        Ok(self.memory.borrow().read(addr))
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) -> Result<(), BusError> {
+        self.cycle()?;
+        self.memory.borrow_mut().write(addr, value);
+        Ok(())
+    }
+
+    fn read_internal(&mut self, addr: u16) -> Result<u8, BusError> {
+        Ok(self.memory.borrow().read(addr))
+    }
+
+    fn write_internal(&mut self, addr: u16, value: u8) -> Result<(), BusError> {
+        self.memory.borrow_mut().write(addr, value);
+        Ok(())
+    }
+
+    // Catches up on the internal M-cycles of the step
+    fn tick(&mut self, t: u8) -> Result<(), BusError> {
+        let t = t.saturating_sub(self.step_t);
+        self.step_t = 0;
+        self.advance(t)
+    }
+}
+
+#[cfg(test)]
+#[derive(Debug)]
+pub struct FlatRAM {
+    pub memory: Box<[u8; 0x10000]>,
+    // T-cycles ticked so far
+    pub t: u64,
+    // Reads and writes made by the CPU's bus cycles so far
+    pub accesses: u64,
+}
+
+#[cfg(test)]
+impl FlatRAM {
+    pub(crate) fn new() -> FlatRAM {
+        FlatRAM {
+            memory: Box::new([0; 0x10000]),
+            t: 0,
+            accesses: 0,
+        }
+    }
+
+    // Copies `data` in starting at `addr`
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        let start = addr as usize;
+        self.memory[start..start + data.len()].copy_from_slice(data);
+    }
+}
+
+#[cfg(test)]
+impl MemoryInterface for FlatRAM {
+    fn read_byte(&mut self, addr: u16) -> Result<u8, BusError> {
+        self.accesses += 1;
+        Ok(self.memory[addr as usize])
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) -> Result<(), BusError> {
+        self.accesses += 1;
+        self.memory[addr as usize] = value;
+        Ok(())
+    }
+
+    fn read_internal(&mut self, addr: u16) -> Result<u8, BusError> {
+        Ok(self.memory[addr as usize])
+    }
+
+    fn write_internal(&mut self, addr: u16, value: u8) -> Result<(), BusError> {
+        self.memory[addr as usize] = value;
+        Ok(())
+    }
+
+    fn tick(&mut self, t: u8) -> Result<(), BusError> {
+        self.t += t as u64;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_flat_ram_words_wrap() {
+    let mut ram = FlatRAM::new();
+    ram.write_word(0xFFFF, 0x1234).unwrap();
+    assert_eq!(ram.memory[0xFFFF], 0x34);
+    assert_eq!(ram.memory[0x0000], 0x12);
+    assert_eq!(ram.read_word(0xFFFF).unwrap(), 0x1234);
+}
@@ -1,6 +1,9 @@
 pub mod cpu;
 pub mod instruction;
+#[cfg(test)]
+mod instruction_test;
 pub mod memory_bus;
+pub mod memory_interface;
 pub mod registers;
 #[cfg(test)]
 mod registers_test;
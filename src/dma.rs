@@ -0,0 +1,102 @@
+/*
+
+OAM DMA (0xFF46)
+
+Writing XX copies 0xXX00 - 0xXX9F to OAM (0xFE00 - 0xFE9F), one byte per
+m-cycle after a one m-cycle start-up: 640 t-cycles in all. Sources from 0xE000
+up read the echo of WRAM.
+
+While the copy runs the DMA owns the bus it reads from, the external bus
+(ROM, cartridge RAM and WRAM) or the video bus. CPU reads on that bus see the
+byte being copied and writes are dropped; OAM (and 0xFEA0 - 0xFEFF) reads
+0xFF. HRAM and the IO registers stay accessible, which is why games run the
+wait loop from HRAM.
+
+*/
+
+pub const DMA_ADDR: u16 = 0xFF46;
+pub const OAM_ADDR: u16 = 0xFE00;
+
+const OAM_LEN: u16 = 0xA0;
+
+#[derive(Debug)]
+pub struct OAMDMA {
+    source: u16,
+    index: u16,
+    // M-cycles left before the first byte is copied
+    delay: u8,
+    // Last byte copied, seen by reads that conflict with the transfer
+    pub byte: u8,
+}
+
+impl OAMDMA {
+    pub fn new(value: u8) -> Self {
+        OAMDMA {
+            source: (value as u16) << 8,
+            index: 0,
+            delay: 1,
+            byte: 0xFF,
+        }
+    }
+
+    // Advances by one m-cycle; returns the (source, destination) of the byte
+    // to copy, if any
+    pub fn step(&mut self) -> Option<(u16, u16)> {
+        if self.delay > 0 {
+            self.delay -= 1;
+            return None;
+        }
+        let source = self.source + self.index;
+        let source = match source {
+            0xE000..=0xFFFF => source - 0x2000,
+            _ => source,
+        };
+        let transfer = (source, OAM_ADDR + self.index);
+        self.index += 1;
+        Some(transfer)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.index == OAM_LEN
+    }
+
+    // True if a CPU access to `addr` conflicts with the transfer
+    pub fn blocks(&self, addr: u16) -> bool {
+        if self.delay > 0 {
+            return false;
+        }
+        match addr {
+            // Includes the unusable area after OAM
+            OAM_ADDR..=0xFEFF => true,
+            0xFF00..=0xFFFF => false,
+            _ => is_video_bus(addr) == is_video_bus(self.source),
+        }
+    }
+}
+
+fn is_video_bus(addr: u16) -> bool {
+    matches!(addr, 0x8000..=0x9FFF)
+}
+
+#[test]
+fn test_dma_timing() {
+    let mut dma = OAMDMA::new(0xC1);
+    assert!(!dma.blocks(0xC000));
+    assert_eq!(dma.step(), None);
+    assert_eq!(dma.step(), Some((0xC100, 0xFE00)));
+    // ROM and WRAM share the external bus, VRAM doesn't
+    assert!(dma.blocks(0x4000));
+    assert!(!dma.blocks(0x8000));
+    assert!(dma.blocks(0xFE10));
+    assert!(!dma.blocks(0xFF80));
+
+    for _ in 1..0xA0 {
+        dma.step();
+    }
+    assert!(dma.is_done());
+
+    // Echo RAM sources read WRAM
+    let mut dma = OAMDMA::new(0xFE);
+    dma.step();
+    assert_eq!(dma.step(), Some((0xDE00, 0xFE00)));
+}
@@ -0,0 +1,145 @@
+use std::{cell::RefCell, rc::Rc, sync::mpsc::Sender};
+
+use crate::{
+    cpu::{
+        cpu::CPU,
+        memory_bus::MemoryBus,
+        memory_interface::{DirectBus, MemoryInterface},
+    },
+    gpu::gpu::GPU,
+    request_response::BusError,
+};
+
+/*
+
+Single-threaded scheduler
+
+Owns the CPU, PPU and memory bus on one thread, as an alternative to running
+each on its own and exchanging t-cycle counts over channels. The PPU sits on
+the CPU's `DirectBus`, which ticks the timer, APU and DMA and steps the PPU
+one m-cycle before each read or write the CPU makes, so an access partway
+through an instruction sees DIV, LY, STAT and DMA as they are at that point.
+The instruction's internal m-cycles (and the idle ones while halted) are
+caught up on at its end. The PPU reads memory through its own `DirectBus`
+over the same `MemoryBus`, so a run depends only on the ROM and the inputs.
+
+Requests from other threads (the event loop saving or recording) are still
+taken over the bus channel and served between frames.
+
+*/
+
+// T-cycles per frame: 154 lines of 456
+pub const FRAME_T: u32 = 70224;
+
+pub type Frame = [[[u8; 4]; 160]; 144];
+
+pub struct GameBoy {
+    // Memory as the CPU sees it, without spending any of its time
+    bus: DirectBus,
+    cpu: CPU<DirectBus>,
+    // T-cycles into the current frame
+    frame_t: u32,
+}
+
+impl GameBoy {
+    // `skip_boot` has to match whether `memory` was given a boot ROM
+    pub fn new(memory: MemoryBus, lcd_sender: Sender<Frame>, skip_boot: bool) -> GameBoy {
+        let bus = DirectBus::new(Rc::new(RefCell::new(memory)));
+        GameBoy::with_bus(bus, lcd_sender, skip_boot)
+    }
+
+    fn with_bus(bus: DirectBus, lcd_sender: Sender<Frame>, skip_boot: bool) -> GameBoy {
+        let ppu = GPU::new(bus.clone(), lcd_sender);
+        let mut cpu = CPU::new(bus.clone().with_ppu(ppu));
+        if skip_boot {
+            cpu.skip_boot();
+        }
+        GameBoy {
+            bus,
+            cpu,
+            frame_t: 0,
+        }
+    }
+
+    // Runs one CPU instruction, with the peripherals and PPU kept in step on
+    // every m-cycle. Returns the t-cycles the instruction took
+    pub fn step(&mut self) -> Result<u8, BusError> {
+        let t = self.cpu.step()?;
+        self.frame_t += t as u32;
+        Ok(t)
+    }
+
+    pub fn run_frame(&mut self) -> Result<(), BusError> {
+        while self.frame_t < FRAME_T {
+            self.step()?;
+        }
+        self.frame_t -= FRAME_T;
+        Ok(())
+    }
+
+    pub fn serve_pending(&mut self) {
+        self.bus.serve_pending();
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc
+    }
+
+    // Reads memory as the CPU sees it
+    pub fn read_byte(&mut self, addr: u16) -> Result<u8, BusError> {
+        self.bus.read_byte(addr)
+    }
+}
+
+// A GameBoy past the boot ROM, running `program` from 0xC100 in WRAM
+#[cfg(test)]
+fn create_gameboy(program: &[u8]) -> (GameBoy, std::sync::mpsc::Receiver<Frame>) {
+    let writes: Vec<_> = (0xC100..).zip(program.iter().copied()).collect();
+    let (lcd_sender, lcd_receiver) = std::sync::mpsc::channel();
+    let mut gameboy = GameBoy::with_bus(DirectBus::with_writes(&writes), lcd_sender, true);
+    gameboy.cpu.pc = 0xC100;
+    (gameboy, lcd_receiver)
+}
+
+#[test]
+fn test_step() {
+    // LD A,0; loop: INC A; LD (0xC000),A; JP loop
+    let program = [0x3E, 0x00, 0x3C, 0xEA, 0x00, 0xC0, 0xC3, 0x02, 0xC1];
+    let (mut first, _first_frames) = create_gameboy(&program);
+    let (mut second, _second_frames) = create_gameboy(&program);
+    let div = first.read_byte(0xFF04).unwrap();
+    let mut t = 0;
+    for _ in 0..1000 {
+        t += first.step().unwrap() as u32;
+        second.step().unwrap();
+    }
+    assert_ne!(first.read_byte(0xC000).unwrap(), 0);
+    // DIV counts every 256 t-cycles from wherever it was in its period
+    let ticks = first.read_byte(0xFF04).unwrap().wrapping_sub(div) as u32;
+    assert!(
+        ticks == t / 256 || ticks == t / 256 + 1,
+        "{ticks} DIV ticks in {t} t-cycles"
+    );
+    // The PPU has caught up: LY counts 456 dot lines from line 0
+    assert_eq!(first.read_byte(0xFF44).unwrap() as u32, t / 456 % 154);
+    // Runs are reproducible
+    assert_eq!(first.pc(), second.pc());
+    assert_eq!(first.read_byte(0xC000), second.read_byte(0xC000));
+}
+
+#[test]
+fn test_access_timing() {
+    // LDH (DIV),A; `nops` x NOP; LD A,(DIV); LD (0xC000),A
+    // DIV is reset by the write on the third m-cycle of LDH and read on the
+    // fourth of LD A,(a16), 4 * `nops` + 16 t-cycles later
+    for (nops, div) in [(59, 0), (60, 1)] {
+        let mut program = vec![0xE0, 0x04];
+        program.extend(std::iter::repeat_n(0x00, nops));
+        program.extend([0xFA, 0x04, 0xFF, 0xEA, 0x00, 0xC0]);
+        let (mut gameboy, _frames) = create_gameboy(&program);
+        for _ in 0..nops + 3 {
+            gameboy.step().unwrap();
+        }
+        assert_eq!(gameboy.read_byte(0xC000).unwrap(), div, "after {nops} NOPs");
+    }
+}
@@ -2,21 +2,26 @@ use std::sync::mpsc::{channel, Receiver, Sender, SyncSender};
 
 use crate::{
     cpu::memory_bus::MemoryBus,
-    request_response::{Bus, Request},
+    cpu::memory_interface::MemoryInterface,
+    interrupt::InterruptFlag,
+    request_response::{Bus, BusError},
 };
 
 use super::{pixel_fifo::PixelFIFO, sprite::Sprite, tile::Tile};
 
 // #[derive(Debug)]
-pub struct GPU {
+pub struct GPU<M = Bus> {
     mode: GPUMode,
     mode_clock: u16,
     pub line: u8,
     tileset: [Tile; 384],
     vram: [u8; 0x2000],
-    visible_sprites: [Option<Sprite>; 10],
-    bus: Bus,
-    fifo: PixelFIFO,
+    // OAM as read at the start of the current scan
+    oam: [Sprite; 40],
+    // Sprites selected for this line with their OAM index, in OAM order
+    visible_sprites: [Option<(u8, Sprite)>; 10],
+    bus: M,
+    fifo: PixelFIFO<M>,
     pallettes: PalletteCollection,
     temp_lcd: [[[u8; 4]; 160]; 144],
     lcd_sender: Sender<[[[u8; 4]; 160]; 144]>,
@@ -24,11 +29,17 @@ pub struct GPU {
     lcd_control_flags: LCDControlFlags,
     scroll: (u8, u8),
     window_pos: (u8, u8),
+    // State of the internal STAT interrupt line; the interrupt is only
+    // requested on a rising edge
+    stat_line: bool,
 }
 
-impl GPU {
-    pub fn new(request_sender: Sender<Request>, lcd_sender: Sender<[[[u8; 4]; 160]; 144]>) -> GPU {
-        let cloned_sender = request_sender.clone();
+const STAT_ADDR: u16 = 0xFF41;
+const LY_ADDR: u16 = 0xFF44;
+const LYC_ADDR: u16 = 0xFF45;
+
+impl<M: MemoryInterface + Clone> GPU<M> {
+    pub fn new(bus: M, lcd_sender: Sender<[[[u8; 4]; 160]; 144]>) -> GPU<M> {
         let pallettes = PalletteCollection {
             background_pallette: Pallette::new(PalletteName::Background),
             sprite_pallette_01: Pallette::new(PalletteName::Sprite01),
@@ -40,9 +51,10 @@ impl GPU {
             line: 0,
             tileset: [Tile::new(); 384],
             vram: [0; 0x2000],
+            oam: [Sprite::from_bytes(0, 0, 0, 0); 40],
             visible_sprites: [None; 10],
-            bus: Bus { request_sender }, // map: false,
-            fifo: PixelFIFO::new(cloned_sender),
+            fifo: PixelFIFO::new(bus.clone()),
+            bus, // map: false,
             pallettes,
             temp_lcd: [[[0; 4]; 160]; 144],
             lcd_sender,
@@ -50,61 +62,65 @@ impl GPU {
             lcd_control_flags: LCDControlFlags::from_byte(0),
             scroll: (0, 0),
             window_pos: (0, 0),
+            stat_line: false,
         }
     }
-    pub fn step(&mut self) -> u8 {
-        // Returns relative time
+    // Returns relative time, or the request the memory thread couldn't serve
+    pub fn step(&mut self) -> Result<u8, BusError> {
         match self.mode {
             GPUMode::OAMRead => {
-                match self.lcd_control_flags.obj_enable {
-                    true => {
-                        // OAM read mode, scanline active
-                        // TODO: Low Priority; find way to 'split up' OAM read into smaller steps
-                        self.oam_search();
-                        self.set_fifo();
-                        self.mode = GPUMode::PixelTransfer;
-                        self.mode_clock += 80;
-                        return 80;
-                    }
-                    false => {
-                        self.mode_clock += 1;
-                        if self.mode_clock == 80 {
-                            self.set_fifo();
-                            self.mode = GPUMode::PixelTransfer;
-                        }
-                        return 1;
+                // OAM read mode, scanline active
+                // Scans one of the 40 OAM entries every 2 dots. Runs whether or
+                // not sprites are enabled, which only affects drawing them
+                if self.mode_clock == 0 {
+                    self.lcd_control()?;
+                    if !self.lcd_control_flags.lcd_enable {
+                        self.switch_off()?;
+                        return Ok(2);
                     }
+                    self.oam = self.read_oam()?;
+                    self.clear_sprites();
                 }
+                self.oam_search((self.mode_clock / 2) as usize);
+                self.mode_clock += 2;
+                if self.mode_clock == 80 {
+                    self.start_pixel_transfer()?;
+                }
+                return Ok(2);
             }
             GPUMode::PixelTransfer => {
                 // VRAM read mode, scanline active
-                // Treat end of mode 3 as end of scanline
+                // Lasts until the FIFO has drawn all 160 pixels: 172 dots plus
+                // SCX % 8 and any window or sprite fetches. HBlank takes up
+                // whatever is left of the 456 dot line
 
                 // Check for window; clear fifo if found
 
-                let line = self.fifo.step(self.temp_lcd[self.line as usize]);
+                let line = self.fifo.step(self.temp_lcd[self.line as usize])?;
                 self.temp_lcd[self.line as usize] = line;
                 if self.fifo.x == 160 {
-                    self.mode = GPUMode::HBlank;
+                    self.set_mode(GPUMode::HBlank)?;
                 }
                 // TODO: Find out why mode_clock is adding w/ overflow
                 if self.mode_clock > 456 {
                     println!("Warning!");
                 }
                 self.mode_clock += 1;
-                return 1;
+                return Ok(1);
             }
             GPUMode::HBlank => {
+                // The line ends on the dot that brings it to 456
+                self.mode_clock += 1;
                 if self.mode_clock < 456 {
-                    self.mode_clock += 1;
-                    return 1;
+                    return Ok(1);
                 }
 
                 self.mode_clock = 0;
 
                 if self.line == 143 {
                     self.line = 0;
-                    self.mode = GPUMode::VBlank;
+                    self.set_mode(GPUMode::VBlank)?;
+                    self.bus.request_interrupt(InterruptFlag::VBlank)?;
                     // TODO: Send temp_LCD to LCD
                     self.lcd_sender.send(self.temp_lcd).unwrap();
                 } else {
@@ -113,79 +129,164 @@ impl GPU {
                     } else {
                         self.line += 1;
                     }
-                    self.mode = GPUMode::OAMRead;
+                    self.set_mode(GPUMode::OAMRead)?;
                     self.fifo.inc_y();
                 }
-                return 1;
+                return Ok(1);
             }
             GPUMode::VBlank => {
                 // Vblank (10 lines)
+                self.mode_clock += 1;
                 if self.mode_clock >= 456 {
                     self.mode_clock = 0;
                     self.line += 1;
 
-                    if self.line == 10 {
+                    // Games usually switch the LCD off during VBlank
+                    self.lcd_control()?;
+                    if !self.lcd_control_flags.lcd_enable {
+                        self.switch_off()?;
+                    } else if self.line < 10 {
+                        self.update_stat()?;
+                    } else {
                         // Restart scanning modes
-                        self.lcd_control();
-                        if self.lcd_control_flags.window_enable {
-                            self.window_pos();
-                        }
-                        if self.lcd_control_flags.lcd_enable {
-                            self.get_scroll();
-                        }
-                        self.mode = GPUMode::OAMRead;
                         self.line = 0;
+                        self.set_mode(GPUMode::OAMRead)?;
                         self.fifo.reset_y();
                     }
                 }
-                return 1;
+                return Ok(1);
+            }
+            GPUMode::Off => {
+                // Polls LCDC once per m-cycle until the LCD is switched back
+                // on, then starts over from line 0
+                self.lcd_control()?;
+                if self.lcd_control_flags.lcd_enable {
+                    self.set_mode(GPUMode::OAMRead)?;
+                    self.fifo.reset_y();
+                }
+                return Ok(4);
             }
         }
     }
 
-    fn oam_search(&mut self) {
-        // All visible sprites added to an array
+    fn switch_off(&mut self) -> Result<(), BusError> {
+        // LY holds at 0 and STAT reports mode 0 while the LCD is off
+        self.line = 0;
+        self.mode_clock = 0;
+        self.set_mode(GPUMode::Off)
+    }
 
-        // Read every entry in OAM
-        let sprite_array = self.read_oam();
-        let mut i = 0;
-        while self.available_sprite_room() && i < 40 {
-            if sprite_array[i].x_coordinate != 0 && sprite_array[i].is_visible(self.line) {
-                self.push_sprites(sprite_array[i]);
-            }
-            i += 1;
+    fn ly(&self) -> u8 {
+        // During VBlank `line` counts the 10 VBlank lines from 0
+        match self.mode {
+            GPUMode::VBlank => 144 + self.line,
+            _ => self.line,
         }
-        // For each entry:
-        // If the X position != 0 AND the y_position intersects current_line AND there's an available
-        // entry in visible_sprites
-        // Add sprite data to visible_sprites
     }
 
-    fn window_pos(&mut self) {
+    fn start_pixel_transfer(&mut self) -> Result<(), BusError> {
+        // Scroll, window position and pallettes are latched per line so they
+        // can change between lines. LCDC was read at the start of the OAM scan
+        self.get_scroll()?;
+        self.window_pos()?;
+        self.read_pallettes()?;
+        self.set_fifo();
+        self.set_mode(GPUMode::PixelTransfer)
+    }
+
+    fn set_mode(&mut self, mode: GPUMode) -> Result<(), BusError> {
+        self.mode = mode;
+        self.update_stat()
+    }
+
+    fn update_stat(&mut self) -> Result<(), BusError> {
+        // Publishes LY and the STAT mode/coincidence bits, and requests an
+        // LCD STAT interrupt when one of the enabled sources becomes active
+        // Bit 6: LYC=LY interrupt source
+        // Bit 5: Mode 2 (OAM) interrupt source
+        // Bit 4: Mode 1 (VBlank) interrupt source
+        // Bit 3: Mode 0 (HBlank) interrupt source
+        // Bit 2: LYC=LY flag
+        // Bit 1-0: Mode
+        let ly = self.ly();
+        self.bus.write_byte(LY_ADDR, ly)?;
+        let lyc = self.bus.read_byte(LYC_ADDR)?;
+        let stat = self.bus.read_byte(STAT_ADDR)?;
+
+        let coincidence = ly == lyc;
+        let new_stat = (stat & 0b0111_1000) | ((coincidence as u8) << 2) | self.mode.to_bits();
+        self.bus.write_byte(STAT_ADDR, new_stat)?;
+
+        let mode_source = match self.mode {
+            GPUMode::HBlank => (stat >> 3) & 1 == 1,
+            GPUMode::VBlank => (stat >> 4) & 1 == 1,
+            GPUMode::OAMRead => (stat >> 5) & 1 == 1,
+            GPUMode::PixelTransfer | GPUMode::Off => false,
+        };
+        let stat_line = !matches!(self.mode, GPUMode::Off)
+            && (mode_source || (coincidence && (stat >> 6) & 1 == 1));
+        if stat_line && !self.stat_line {
+            self.bus.request_interrupt(InterruptFlag::LCDStat)?;
+        }
+        self.stat_line = stat_line;
+        Ok(())
+    }
+
+    fn oam_search(&mut self, index: usize) {
+        // Selects OAM entry `index` if it's on the current line and there's
+        // room for it. Only Y is checked: sprites at X = 0 are offscreen but
+        // still count towards the 10 per line
+        let sprite = self.oam[index];
+        if self.available_sprite_room()
+            && sprite.is_visible(self.line, self.lcd_control_flags.obj_height())
+        {
+            self.push_sprites(index as u8, sprite);
+        }
+    }
+
+    // The sprites selected by the last OAM scan with their OAM index, in OAM
+    // order
+    pub fn selected_sprites(&self) -> impl Iterator<Item = (u8, Sprite)> + '_ {
+        self.visible_sprites.iter().map_while(|entry| *entry)
+    }
+
+    fn window_pos(&mut self) -> Result<(), BusError> {
         // Fetch window x pos(0xFF4B), window y pos(0xFF4A)
-        let data = self.bus.read_word(0xFF4A);
-        let y = ((data >> 8) & 0xFF) as u8;
-        let x = (data & 0xFF) as u8;
+        let data = self.bus.read_word(0xFF4A)?;
+        let y = (data & 0xFF) as u8;
+        let x = ((data >> 8) & 0xFF) as u8;
 
         self.window_pos = (x, y);
 
         // Called before FIFO steps
-        // Not called if window is disabled
+        Ok(())
     }
 
-    fn get_scroll(&mut self) {
+    fn get_scroll(&mut self) -> Result<(), BusError> {
         // TODO: fetch scroll y(0xFF42), scroll x (0xFF43)
-        let data = self.bus.read_word(0xFF42);
-        let y = ((data >> 8) & 0xFF) as u8;
-        let x = (data & 0xFF) as u8;
+        let data = self.bus.read_word(0xFF42)?;
+        let y = (data & 0xFF) as u8;
+        let x = ((data >> 8) & 0xFF) as u8;
 
         self.scroll = (x, y);
 
         // Called before FIFO steps
+        Ok(())
     }
 
-    fn lcd_control(&mut self) {
-        let data = self.bus.read_byte(0xFF40);
+    fn read_pallettes(&mut self) -> Result<(), BusError> {
+        // BGP (0xFF47), OBP0 (0xFF48), OBP1 (0xFF49)
+        let data = self.bus.read_bytes(0xFF47, 3)?;
+        self.pallettes = PalletteCollection {
+            background_pallette: Pallette::from_byte(PalletteName::Background, data[0]),
+            sprite_pallette_01: Pallette::from_byte(PalletteName::Sprite01, data[1]),
+            sprite_pallette_02: Pallette::from_byte(PalletteName::Sprite02, data[2]),
+        };
+        Ok(())
+    }
+
+    fn lcd_control(&mut self) -> Result<(), BusError> {
+        let data = self.bus.read_byte(0xFF40)?;
         self.lcd_control_flags = LCDControlFlags::from_byte(data);
         // Bit 7: LCD / PPU enable
         // Bit 6: Window Tile Map Area, 0= 0x9800-0x9BFF, 1= 0x9C00-0x9FFF
@@ -197,21 +298,17 @@ impl GPU {
         // Bit 1: OBJ enable: 0= OFF, 1=ON
         // TODO: figure out what this means
         // Bit 0: BG / Window enable/priority(?): 0= OFF, 1=ON
+        Ok(())
     }
 
-    fn read_oam(&self) -> [Sprite; 40] {
+    fn read_oam(&mut self) -> Result<[Sprite; 40], BusError> {
         // requests memory access
-        let data = self.bus.read_oam();
+        let data = self.bus.read_bytes(0xFE00, 160)?;
         let mut new_sprite_array = [Sprite::from_bytes(0, 0, 0, 0); 40];
-        let mut i = 0;
-        for sprite in data.chunks_exact(4) {
-            while i < 40 {
-                new_sprite_array[i] =
-                    Sprite::from_bytes(sprite[0], sprite[1], sprite[2], sprite[3]);
-                i += 1;
-            }
+        for (i, sprite) in data.chunks_exact(4).enumerate() {
+            new_sprite_array[i] = Sprite::from_bytes(sprite[0], sprite[1], sprite[2], sprite[3]);
         }
-        new_sprite_array
+        Ok(new_sprite_array)
     }
 
     fn available_sprite_room(&self) -> bool {
@@ -226,10 +323,11 @@ impl GPU {
         self.visible_sprites = [None; 10];
     }
 
-    fn push_sprites(&mut self, sprite: Sprite) {
+    fn push_sprites(&mut self, index: u8, sprite: Sprite) {
         // Assumes the last item in visible_sprites is None
-        self.visible_sprites.rotate_right(1);
-        self.visible_sprites[0] = Some(sprite);
+        if let Some(slot) = self.visible_sprites.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some((index, sprite));
+        }
     }
 
     pub fn read_vram(&self, addr: u16) -> u8 {
@@ -268,25 +366,33 @@ impl GPU {
     }
 
     fn set_fifo(&mut self) {
-        // self.fifo.clear();
+        let mut sprites = [None; 10];
         if self.lcd_control_flags.obj_enable {
-            self.fifo.set_sprites(self.visible_sprites);
-        } else {
-            self.fifo.set_sprites([None; 10])
+            for (slot, (_, sprite)) in sprites.iter_mut().zip(self.selected_sprites()) {
+                *slot = Some(sprite);
+            }
         }
+        self.fifo.set_sprites(sprites);
+        self.fifo
+            .set_obj_height(self.lcd_control_flags.obj_height());
         self.fifo.set_pallettes(self.pallettes);
         self.fifo
             .set_bg_tile_map_addr(self.lcd_control_flags.bg_tile_map_area);
-        if self.lcd_control_flags.bg_window_enable_priority {
-            self.fifo.set_window_enable(true);
-            self.fifo
-                .set_window_tile_map_addr(self.lcd_control_flags.window_tile_map_area);
-            self.fifo.set_window_pos(self.window_pos);
-        } else {
-            self.fifo.set_window_enable(false);
-        }
+        self.fifo
+            .set_window_bg_tile_data_area_addr(self.lcd_control_flags.bg_window_tile_data_area);
+        // With LCDC bit 0 clear the background is blank and the window isn't
+        // drawn at all
+        self.fifo
+            .set_bg_window_enable(self.lcd_control_flags.bg_window_enable_priority);
+        self.fifo.set_window_enable(
+            self.lcd_control_flags.window_enable
+                && self.lcd_control_flags.bg_window_enable_priority,
+        );
+        self.fifo
+            .set_window_tile_map_addr(self.lcd_control_flags.window_tile_map_area);
+        self.fifo.set_window_pos(self.window_pos);
         self.fifo.set_scroll(self.scroll);
-        self.fifo.reset_x();
+        self.fifo.clear();
     }
 
     // fn reset_tileset(&mut self) {
@@ -311,6 +417,19 @@ enum GPUMode {
     PixelTransfer,
     HBlank,
     VBlank,
+    // LCDC bit 7 clear
+    Off,
+}
+
+impl GPUMode {
+    fn to_bits(&self) -> u8 {
+        match self {
+            GPUMode::HBlank | GPUMode::Off => 0,
+            GPUMode::VBlank => 1,
+            GPUMode::OAMRead => 2,
+            GPUMode::PixelTransfer => 3,
+        }
+    }
 }
 
 // #[cfg(test)]
@@ -373,23 +492,15 @@ impl Pallette {
     }
 
     pub fn from_byte(name: PalletteName, data: u8) -> Self {
-        let mut temp_pallette: [u8; 4] = [0; 4];
-        let mut i = 7;
-        let l = 0;
-        while l < 4 {
-            // TODO: Check that pallette byte is correctly read
-            temp_pallette[l] = (((data >> i) & 1) << 1) + ((data >> i - 1) & 1);
-
-            i += 1;
-            i -= 2
-        }
+        // Bits 7-6 give the shade of colour 3, down to bits 1-0 for colour 0
+        let shade = |colour: u8| Color::new((data >> (colour * 2)) & 0b11);
 
         Pallette {
             name,
-            color_11: Color::new(temp_pallette[0]),
-            color_10: Color::new(temp_pallette[1]),
-            color_01: Color::new(temp_pallette[2]),
-            color_00: Color::new(temp_pallette[3]),
+            color_11: shade(3),
+            color_10: shade(2),
+            color_01: shade(1),
+            color_00: shade(0),
         }
     }
 
@@ -433,6 +544,14 @@ struct LCDControlFlags {
 }
 
 impl LCDControlFlags {
+    fn obj_height(&self) -> u8 {
+        if self.obj_size {
+            16
+        } else {
+            8
+        }
+    }
+
     pub fn from_byte(data: u8) -> LCDControlFlags {
         let lcd_enable = (data >> 7) == 1;
         let window_tile_map_area = {
@@ -472,3 +591,76 @@ impl LCDControlFlags {
         }
     }
 }
+
+#[cfg(test)]
+use crate::cpu::memory_interface::DirectBus;
+
+// A GPU about to start the OAM scan of line 5, after `writes` to memory
+#[cfg(test)]
+fn create_gpu(writes: &[(u16, u8)]) -> GPU<DirectBus> {
+    let (lcd_sender, _) = channel();
+    let mut gpu = GPU::new(DirectBus::with_writes(writes), lcd_sender);
+    gpu.mode = GPUMode::OAMRead;
+    gpu.line = 5;
+    gpu
+}
+
+#[test]
+fn test_oam_scan() {
+    // LCD on with 8x16 sprites
+    let mut writes = vec![(0xFF40, 0b1000_0110)];
+    // Entry 0 ends just above line 5, entry 1 is 16 tall and reaches it.
+    // Entries 2-13 are all on the line; entry 2 at X = 0 still counts
+    writes.push((0xFE00, 5));
+    writes.push((0xFE04, 6));
+    for i in 2..14u16 {
+        writes.push((0xFE00 + i * 4, 16));
+        writes.push((0xFE00 + i * 4 + 1, ((i - 2) * 8) as u8));
+    }
+    let mut gpu = create_gpu(&writes);
+
+    let mut t = 0;
+    while matches!(gpu.mode, GPUMode::OAMRead) {
+        t += gpu.step().unwrap() as u32;
+    }
+    assert_eq!(t, 80);
+
+    let selected: Vec<u8> = gpu.selected_sprites().map(|(index, _)| index).collect();
+    assert_eq!(selected, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+    let (_, sprite) = gpu.selected_sprites().nth(1).unwrap();
+    assert_eq!(sprite.x_coordinate, 0);
+}
+
+#[test]
+fn test_line_length() {
+    for scx in [0, 5] {
+        // LCD and background on
+        let mut gpu = create_gpu(&[(0xFF40, 0b1001_0001), (0xFF43, scx)]);
+        let mut t = [0u32; 4];
+        while gpu.line == 5 {
+            let mode = gpu.mode.to_bits() as usize;
+            t[mode] += gpu.step().unwrap() as u32;
+        }
+        let [hblank, _, oam, transfer] = t;
+        assert_eq!(oam, 80);
+        assert_eq!(transfer, 172 + scx as u32);
+        assert_eq!(oam + transfer + hblank, 456, "SCX = {scx}");
+    }
+}
+
+#[test]
+fn test_lcd_off() {
+    let mut gpu = create_gpu(&[(0xFF40, 0b0001_0001)]);
+    for _ in 0..100 {
+        gpu.step().unwrap();
+        assert!(matches!(gpu.mode, GPUMode::Off));
+        assert_eq!(gpu.bus.read_byte(LY_ADDR).unwrap(), 0);
+        assert_eq!(gpu.bus.read_byte(STAT_ADDR).unwrap() & 0b11, 0);
+    }
+
+    gpu.bus.write_byte(0xFF40, 0b1001_0001).unwrap();
+    gpu.step().unwrap();
+    assert!(matches!(gpu.mode, GPUMode::OAMRead));
+    assert_eq!(gpu.line, 0);
+    assert_eq!(gpu.bus.read_byte(STAT_ADDR).unwrap() & 0b11, 2);
+}
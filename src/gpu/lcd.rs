@@ -1,5 +1,8 @@
 // use std::sync::mpsc::Receiver;
 
+use std::fs;
+use std::io;
+use std::path::Path;
 use std::sync::mpsc::{Receiver, TryRecvError};
 
 use pixels::{Pixels, SurfaceTexture};
@@ -78,6 +81,15 @@ impl LCD {
         self.pixels.render().unwrap();
     }
 
+    // Writes the current frame as a binary PPM
+    pub fn screenshot(&self, path: &Path) -> io::Result<()> {
+        let mut data = format!("P6\n{WIDTH} {HEIGHT}\n255\n").into_bytes();
+        for pixel in self.pixels.get_frame().chunks_exact(4) {
+            data.extend_from_slice(&pixel[..3]);
+        }
+        fs::write(path, data)
+    }
+
     pub fn resize(&mut self, size: PhysicalSize<u32>) {
         self.pixels.resize_surface(size.width, size.height);
         self.render();
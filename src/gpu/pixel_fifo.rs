@@ -1,14 +1,19 @@
-use std::sync::mpsc::Sender;
-
-use crate::request_response::{Bus, Request};
+use crate::{
+    cpu::memory_interface::MemoryInterface,
+    request_response::{Bus, BusError},
+};
 
 use super::gpu::{Pallette, PalletteCollection, PalletteName};
 use super::sprite::Sprite;
-pub struct PixelFIFO {
+pub struct PixelFIFO<M = Bus> {
     fifo: [Option<PixelData>; 16],
-    t: u8,
-    fetcher: Fetcher,
+    // Sprite pixels lined up with the first 8 of `fifo`, mixed in as they're
+    // shifted out
+    sprite_fifo: [Option<PixelData>; 8],
+    fetcher: Fetcher<M>,
     visible_sprites: [Option<Sprite>; 10],
+    // 8, or 16 for 8x16 sprites (LCDC bit 2)
+    obj_height: u8,
     pub x: u8,
     pub y: u8,
     // background_pallette: Pallette,
@@ -20,16 +25,32 @@ pub struct PixelFIFO {
     scroll: (u8, u8),
     window_pos: (u8, u8),
     window_enable: bool,
+    // LCDC bit 0: when clear the background and window draw as colour 0, so
+    // sprites are drawn over them whatever their priority flag
+    bg_window_enable: bool,
     window_mode: bool,
+    // Set once LY has matched WY this frame; the window can only be drawn
+    // from then on
+    window_y_triggered: bool,
+    // The window's own line counter, which only advances on lines the
+    // window was drawn on
+    window_line: u8,
     window_bg_tile_data_area_addr: u16,
+    // Pixels still to be dropped for the fine horizontal scroll (SCX % 8)
+    discard: u8,
+    // Dots the FIFO sits idle for before doing anything else
+    stall: u16,
+    // Tile column of the last sprite fetched, as only the first sprite in a
+    // column waits for the background fetch
+    sprite_tile: Option<u8>,
 }
 
 // TODO: Get fifo to work w/ new pallette object
 
-impl PixelFIFO {
+impl<M: MemoryInterface> PixelFIFO<M> {
     pub fn new(
         // lcd_sender: Sender<PixelData>,
-        request_sender: Sender<Request>,
+        bus: M,
         // background_pallette: Pallette,
         // sprite_pallette_01: Pallette,
         // sprite_pallette_02: Pallette,
@@ -41,14 +62,11 @@ impl PixelFIFO {
         };
         PixelFIFO {
             fifo: [None; 16],
+            sprite_fifo: [None; 8],
             // lcd_sender,
-            t: 0,
-            fetcher: Fetcher::new(
-                pallettes.background_pallette.clone(),
-                Bus { request_sender },
-                0,
-            ),
+            fetcher: Fetcher::new(pallettes.background_pallette, bus),
             visible_sprites: [None; 10],
+            obj_height: 8,
             x: 0,
             y: 0,
             pallettes,
@@ -58,12 +76,30 @@ impl PixelFIFO {
             scroll: (0, 0),
             window_pos: (0, 0),
             window_enable: false,
+            bg_window_enable: true,
             window_mode: false,
+            window_y_triggered: false,
+            window_line: 0,
+            discard: 0,
+            stall: 0,
+            sprite_tile: None,
         }
     }
 
-    pub fn step(&mut self, line: [[u8; 4]; 160]) -> [[u8; 4]; 160] {
-        // TODO: if x = 0, discard the first scrollx pixels
+    // Advances the FIFO and the fetcher by one dot, drawing into `line`
+    pub fn step(&mut self, line: [[u8; 4]; 160]) -> Result<[[u8; 4]; 160], BusError> {
+        // Fetching a sprite stalls the FIFO
+        if self.discard == 0 {
+            while let Some(sprite) = self.sprite_check() {
+                self.stall += self.sprite_penalty(sprite);
+                self.fetch_sprite(sprite)?;
+            }
+        }
+
+        if self.stall > 0 {
+            self.stall -= 1;
+            return Ok(line);
+        }
 
         // Check to see if just entered window mode
         if self.check_window_switch() {
@@ -72,45 +108,72 @@ impl PixelFIFO {
             self.fifo = [None; 16];
             // reset fetch w/ window map
             self.fetcher.clear();
-            self.fetcher.set_map_addr(self.window_tile_map_addr)
+            self.fetcher.x = 0;
+            // With WX below 7 the window starts off the left edge of the screen
+            self.discard = 7u8.saturating_sub(self.window_pos.0);
         }
 
-        let mut new_line = line;
-
-        new_line = self.push(new_line);
+        let new_line = self.push(line);
+        self.fetch()?;
 
-        if self.t % 2 == 0 && self.t <= 4 {
-            // Conditional fetch steps on cycle 0, 2, and 4
-            self.fetcher.step();
-        }
+        Ok(new_line)
+    }
 
-        if self.t == 7 {
-            // On last cycle, enqueue data from fetcher
-            self.enqueue();
-            self.t = 0;
+    // Advances the background/window fetcher by one dot
+    fn fetch(&mut self) -> Result<(), BusError> {
+        let (map_addr, tile_row) = if self.window_mode {
+            (self.get_current_window_addr(), self.window_line % 8)
         } else {
-            self.t += 1;
+            (
+                self.get_current_bg_addr(),
+                self.y.wrapping_add(self.scroll.1) % 8,
+            )
+        };
+        // The fetcher can only push while the FIFO holds 8 pixels or fewer
+        let room = self.fifo[8].is_none();
+        if let Some(pixels) = self.fetcher.step(map_addr, tile_row, room)? {
+            self.enqueue(pixels);
         }
-
-        new_line
+        Ok(())
     }
 
     fn push(&mut self, line: [[u8; 4]; 160]) -> [[u8; 4]; 160] {
-        // !!!
-        // FIFO must contain more than 8 pixels to be able to push something out
-        // !!!
-        if let None = self.fifo[8] {
-            return line;
-        };
+        // Shifts out one pixel per dot while the FIFO isn't empty
         match self.fifo[0] {
             None => {
                 return line;
             }
-            Some(pixel_data) => {
+            Some(bg_pixel) => {
                 let mut new_line = line;
-                new_line[self.x as usize] = pixel_data.to_rgba();
                 self.fifo[0] = None;
                 self.fifo.rotate_left(1);
+                let sprite_pixel = self.sprite_fifo[0].take();
+                self.sprite_fifo.rotate_left(1);
+                let bg_pixel = match self.bg_window_enable {
+                    true => bg_pixel,
+                    false => PixelData {
+                        data: 0,
+                        ..bg_pixel
+                    },
+                };
+                // The first SCX % 8 pixels of the line are shifted out but
+                // not drawn
+                if self.discard > 0 {
+                    self.discard -= 1;
+                    return new_line;
+                }
+                // Colour 0 sprite pixels are transparent, and sprites with the
+                // priority flag set are drawn behind background colours 1-3
+                let pixel_data = match sprite_pixel {
+                    Some(sprite_pixel)
+                        if sprite_pixel.data != 0
+                            && !(sprite_pixel.bg_priority && bg_pixel.data != 0) =>
+                    {
+                        sprite_pixel
+                    }
+                    _ => bg_pixel,
+                };
+                new_line[self.x as usize] = pixel_data.to_rgba();
                 self.x += 1;
                 return new_line;
             }
@@ -122,84 +185,113 @@ impl PixelFIFO {
 
         // Sprite cooordinates start 0x08 pixels to the right, and 0x10 pixels down
 
-        // Returns first sprite and sets it to None in the array
-        let mut active_sprite = None;
-        let mut i = 0;
-        while i < 10 {
-            let sprite = self.visible_sprites[i];
-            match sprite {
-                None => {
-                    i += 1;
-                }
-                Some(sprite) => {
-                    if sprite.x_coordinate - 0x08 != self.x {
-                        i += 1;
-                        continue;
-                    }
+        // Returns the next sprite starting at this pixel and sets it to None
+        // in the array. Sprites with X below 8 all start at pixel 0, so they're
+        // taken in order of X and then OAM index like the rest
+        let (i, sprite) = self
+            .visible_sprites
+            .iter()
+            .enumerate()
+            .filter_map(|(i, sprite)| sprite.map(|sprite| (i, sprite)))
+            .filter(|(_, sprite)| sprite.x_coordinate.saturating_sub(0x08) == self.x)
+            .min_by_key(|&(i, sprite)| (sprite.x_coordinate, i))?;
+        self.visible_sprites[i] = None;
+        Some(sprite)
+    }
 
-                    active_sprite = Some(sprite);
-                    self.visible_sprites[i] = None;
-                    break;
-                }
-            }
-        }
-        active_sprite
-    }
-
-    fn sprite_overlay(&mut self, sprite_pixels: [PixelData; 8]) {
-        for i in 0..9 {
-            match self.fifo[i] {
-                None => panic!("Attempting to overlay a sprite onto empty pixels"),
-                Some(base_pixel) => match base_pixel.pallette.name {
-                    PalletteName::Background => {
-                        if sprite_pixels[i].data != 0 {
-                            self.fifo[i] = Some(sprite_pixels[i]);
-                        }
-                    }
-                    _ => {}
-                },
-            }
+    fn sprite_penalty(&mut self, sprite: Sprite) -> u16 {
+        // 6 dots for the sprite fetch itself. The first sprite in a tile
+        // column also waits for the background fetch of that tile, up to 5
+        // dots depending on how far into the tile the sprite starts
+        let x = sprite.x_coordinate.saturating_sub(0x08);
+        let tile_x = x.wrapping_add(self.scroll.0 % 8);
+        let tile = tile_x / 8;
+        if self.sprite_tile == Some(tile) {
+            return 6;
         }
+        self.sprite_tile = Some(tile);
+        6 + 5u16.saturating_sub((tile_x % 8) as u16)
     }
 
-    fn enqueue(&mut self) {
-        // Checks to see if the data from self.fifo[0] or self.fifo[8] is None
-        let mut i = match (self.fifo[0], self.fifo[8]) {
-            (Some(_), Some(_)) => {
-                // Do Nothing
-                9
-            }
-            (None, _) => {
-                // Fill in starting with i = 0
-                0
-            }
-            (Some(_), None) => {
-                // Fill in starting with i = 8
-                8
-            }
+    fn fetch_sprite(&mut self, sprite: Sprite) -> Result<(), BusError> {
+        // The sprite is mixed over the next 8 background pixels, so the
+        // background fetch has to finish first. The time that takes is part of
+        // the sprite's penalty
+        while self.fifo[7].is_none() {
+            self.fetch()?;
+        }
+
+        let addr = self.get_current_sprite_addr(sprite);
+        let data_0 = self.fetcher.bus.read_byte(addr)?;
+        let data_1 = self.fetcher.bus.read_byte(addr + 1)?;
+        let pallette = match sprite.palette {
+            false => self.pallettes.sprite_pallette_01,
+            true => self.pallettes.sprite_pallette_02,
         };
-        // In either case, fill each item with the data from the fetcher
-        if i != 9 {
-            let new_pixel_data = self.fetcher.push();
-            let mut l = 0;
-            while l < 8 {
-                self.fifo[i] = new_pixel_data[l];
-                i += 1;
-                l += 1;
+
+        let mut sprite_pixels = [PixelData {
+            data: 0,
+            pallette,
+            bg_priority: sprite.priority,
+        }; 8];
+        for (i, pixel) in sprite_pixels.iter_mut().enumerate() {
+            let bit = if sprite.x_flip { i } else { 7 - i };
+            pixel.data = (((data_1 >> bit) & 1) << 1) | ((data_0 >> bit) & 1);
+        }
+
+        // Sprites with X below 8 are cut off by the left edge of the screen
+        let hidden = 8 - sprite.x_coordinate.min(8) as usize;
+        self.sprite_overlay(sprite_pixels, hidden);
+        Ok(())
+    }
+
+    fn sprite_overlay(&mut self, sprite_pixels: [PixelData; 8], hidden: usize) {
+        // Sprites are fetched in order of X, then OAM index, so a pixel already
+        // taken by an earlier sprite has priority over this one
+        for (i, pixel) in sprite_pixels.into_iter().enumerate().skip(hidden) {
+            let slot = &mut self.sprite_fifo[i - hidden];
+            let taken = matches!(slot, Some(taken) if taken.data != 0);
+            if pixel.data != 0 && !taken {
+                *slot = Some(pixel);
             }
         }
     }
 
-    // pub fn clear(&mut self) {
-    //     self.fifo = [None; 16];
-    //     self.t = 0;
-    //     self.fetcher.clear();
-    // }
+    fn enqueue(&mut self, pixels: [PixelData; 8]) {
+        // Appends the fetched pixels after the last occupied slot; the fetcher
+        // only pushes when that's at most slot 8
+        let start = self.fifo.iter().take_while(|pixel| pixel.is_some()).count();
+        for (i, pixel) in pixels.into_iter().enumerate() {
+            self.fifo[start + i] = Some(pixel);
+        }
+    }
+
+    // Empties the FIFO and restarts the fetcher at the left edge of the line.
+    // Call once the scroll for the line is set
+    pub fn clear(&mut self) {
+        self.fifo = [None; 16];
+        self.sprite_fifo = [None; 8];
+        self.fetcher.clear();
+        self.fetcher.x = 0;
+        self.window_mode = false;
+        self.x = 0;
+        self.discard = self.scroll.0 % 8;
+        if self.y == self.window_pos.1 {
+            self.window_y_triggered = true;
+        }
+        // The first tile of a line is fetched twice, costing one extra fetch
+        self.stall = 6;
+        self.sprite_tile = None;
+    }
 
     pub fn set_sprites(&mut self, sprite_list: [Option<Sprite>; 10]) {
         self.visible_sprites = sprite_list;
     }
 
+    pub fn set_obj_height(&mut self, height: u8) {
+        self.obj_height = height;
+    }
+
     pub fn set_pallettes(&mut self, pallettes: PalletteCollection) {
         self.pallettes = pallettes;
         self.fetcher
@@ -224,89 +316,116 @@ impl PixelFIFO {
 
     pub fn set_window_bg_tile_data_area_addr(&mut self, addr: u16) {
         self.window_bg_tile_data_area_addr = addr;
+        self.fetcher.set_tile_data_addr(addr);
     }
 
     pub fn set_window_enable(&mut self, enable: bool) {
         self.window_enable = enable;
     }
 
-    pub fn reset_x(&mut self) {
-        self.x = 0;
+    pub fn set_bg_window_enable(&mut self, enable: bool) {
+        self.bg_window_enable = enable;
     }
 
     pub fn inc_y(&mut self) {
         self.y += 1;
+        if self.window_mode {
+            self.window_line += 1;
+        }
     }
 
     pub fn reset_y(&mut self) {
         self.y = 0;
+        self.window_y_triggered = false;
+        self.window_line = 0;
     }
 
     fn check_window_switch(&self) -> bool {
-        if !self.window_enable {
+        if !self.window_enable || !self.window_y_triggered {
             return false;
         }
 
         // If we're already in window mode, we don't need to switch to window mode
-        if self.window_mode == true {
+        if self.window_mode {
             return false;
         }
 
-        let x_check = self.x >= self.window_pos.0;
-        let y_check = self.y >= self.window_pos.1;
-
-        if x_check && y_check {
-            return true;
-        } else {
-            return false;
-        }
+        // WX is the window's left edge plus 7
+        self.x as u16 + 7 >= self.window_pos.0 as u16
     }
 
     fn get_current_bg_addr(&self) -> u16 {
-        let current_tile_row_addr = ((self.scroll.1 as u16 + self.y as u16) / 8) * 32;
+        // The map is 32x32 tiles and wraps around in both directions
+        let current_tile_row_addr = (self.y.wrapping_add(self.scroll.1) / 8) as u16 * 32;
 
-        let tile_area_addr = current_tile_row_addr + ((self.x as u16 + self.scroll.0 as u16) / 8);
+        let tile_area_addr =
+            current_tile_row_addr + (self.fetcher.x.wrapping_add(self.scroll.0) / 8) as u16;
 
-        return self.bg_tile_map_addr + tile_area_addr;
+        self.bg_tile_map_addr + tile_area_addr
     }
 
     fn get_current_window_addr(&self) -> u16 {
-        if self.x < self.window_pos.0 || self.y < self.window_pos.1 {
-            panic!("Trying to access window mode when outside window position")
-        }
-
-        let window_x = self.x - self.window_pos.0;
-        let window_y = self.y - self.window_pos.1;
+        // The window map is 32 tiles wide like the background's, and is drawn
+        // from its top left corner wherever the window starts
+        let current_tile_row_addr = (self.window_line / 8) as u16 * 32;
 
-        // Based upon the assumption the window map is 20x18 tiles
-
-        let i = ((window_y as u16 / 8) * 20) + (window_x as u16 / 8);
-        self.window_tile_map_addr + i
+        self.window_tile_map_addr + current_tile_row_addr + (self.fetcher.x / 8) as u16
     }
 
     fn get_current_sprite_addr(&mut self, sprite: Sprite) -> u16 {
-        // Assumes sprite is visible at the x/y coordinates (checked in step)
-        return sprite.tile_number as u16 + 0x8000;
+        // Assumes sprite is visible at the x/y coordinates (checked in step).
+        // Returns the address of the sprite's row on this line
+        let mut row = self.y.wrapping_add(16).wrapping_sub(sprite.y_coordinate);
+        if sprite.y_flip {
+            row = self.obj_height - 1 - row;
+        }
+        // 8x16 sprites use an even/odd pair of tiles, ignoring bit 0
+        let tile_number = match self.obj_height {
+            16 => sprite.tile_number & 0xFE,
+            _ => sprite.tile_number,
+        };
+        0x8000 + tile_number as u16 * 16 + row as u16 * 2
     }
 }
 
-struct Fetcher {
-    map_addr: u16,
-    tile_addr: Option<u8>,
-    data_0: Option<u8>,
-    data_1: Option<u8>,
+// The steps of a tile fetch. GetTile, DataLow and DataHigh take two dots
+// each; the fetcher then sleeps until the FIFO has room and pushes the row
+// on that same dot, so a fetch takes 6 dots when the FIFO keeps up
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FetchState {
+    GetTile,
+    DataLow,
+    DataHigh,
+    Sleep,
+}
+
+struct Fetcher<M> {
+    state: FetchState,
+    // Set on the first of a step's two dots
+    waiting: bool,
+    // Pixels fetched so far on this line
+    x: u8,
+    // 0x8000 (unsigned tile numbers) or 0x8800 (signed, based at 0x9000)
+    tile_data_addr: u16,
+    tile_number: u8,
+    tile_row: u8,
+    data_0: u8,
+    data_1: u8,
     pallette: Pallette,
-    bus: Bus,
+    bus: M,
 }
 
-impl Fetcher {
-    pub fn new(pallette: Pallette, bus: Bus, map_addr: u16) -> Self {
-        // Constructed every new fetch
+impl<M: MemoryInterface> Fetcher<M> {
+    pub fn new(pallette: Pallette, bus: M) -> Self {
         Fetcher {
-            map_addr,
-            tile_addr: None,
-            data_0: None,
-            data_1: None,
+            state: FetchState::GetTile,
+            waiting: false,
+            x: 0,
+            tile_data_addr: 0x8000,
+            tile_number: 0,
+            tile_row: 0,
+            data_0: 0,
+            data_1: 0,
             pallette,
             bus,
         }
@@ -316,67 +435,79 @@ impl Fetcher {
         self.pallette = pallete;
     }
 
-    pub fn clear(&mut self) {
-        self.tile_addr = None;
-        self.data_0 = None;
-        self.data_1 = None;
+    pub fn set_tile_data_addr(&mut self, addr: u16) {
+        self.tile_data_addr = addr;
     }
 
-    pub fn set_map_addr(&mut self, map_addr: u16) {
-        self.map_addr = map_addr;
+    // Abandons the current fetch; the next step starts a new one
+    pub fn clear(&mut self) {
+        self.state = FetchState::GetTile;
+        self.waiting = false;
     }
 
-    pub fn step(&mut self) {
-        match self.tile_addr {
-            None => {
-                // TODO: Fetch tile_addr
-
-                // Read Background Map based on window x,y and lcd x, y(line)
+    // Advances the fetch by one dot. `map_addr` is the tile map entry to read
+    // and `tile_row` the row within that tile; both are only used by GetTile.
+    // Returns a row of 8 pixels once pushed, which needs `room` in the FIFO
+    pub fn step(
+        &mut self,
+        map_addr: u16,
+        tile_row: u8,
+        room: bool,
+    ) -> Result<Option<[PixelData; 8]>, BusError> {
+        if self.state != FetchState::Sleep {
+            self.waiting = !self.waiting;
+            if self.waiting {
+                return Ok(None);
             }
-            Some(tile_addr) => match self.data_0 {
-                None => {
-                    // TODO: Fetch data_0
-                }
-                Some(data_0) => match self.data_1 {
-                    None => {
-                        // TODO: Fetch data_1
-                    }
-                    Some(data_1) => {
-                        // TOPDO: Returns Pixel Data
-                    }
-                },
-            },
         }
-    }
 
-    pub fn push(&mut self) -> [Option<PixelData>; 8] {
-        match (self.data_0, self.data_1) {
-            (Some(data_0), Some(data_1)) => {
-                let pixel_data = self.construct_pixel_data(data_0, data_1);
-                self.clear();
-                pixel_data
+        match self.state {
+            FetchState::GetTile => {
+                self.tile_number = self.bus.read_byte(map_addr)?;
+                self.tile_row = tile_row;
+                self.state = FetchState::DataLow;
+            }
+            FetchState::DataLow => {
+                self.data_0 = self.bus.read_byte(self.tile_data_row_addr())?;
+                self.state = FetchState::DataHigh;
             }
-            _ => [None; 8],
+            FetchState::DataHigh => {
+                self.data_1 = self.bus.read_byte(self.tile_data_row_addr() + 1)?;
+                self.state = FetchState::Sleep;
+            }
+            FetchState::Sleep => {}
         }
-    }
 
-    fn construct_pixel_data(&self, data_0: u8, data_1: u8) -> [Option<PixelData>; 8] {
-        let mut i = 7;
-        let mut l = 0;
-        let mut return_data: [Option<PixelData>; 8] = [None; 8];
-        while i <= 0 {
-            // TODO: Check to make sure that data_0 is the 2nd significant digit
-            let data = (((data_0 >> i) & 1) << 1) + ((data_1 >> i) & 1);
+        if self.state != FetchState::Sleep || !room {
+            return Ok(None);
+        }
+        self.state = FetchState::GetTile;
+        self.x = self.x.wrapping_add(8);
+        Ok(Some(self.construct_pixel_data()))
+    }
 
-            return_data[l] = Some(PixelData {
-                data,
-                pallette: self.pallette,
-            });
+    fn tile_data_row_addr(&self) -> u16 {
+        // Each tile is 16 bytes, two per row
+        let tile_addr = match self.tile_data_addr {
+            0x8000 => 0x8000 + self.tile_number as u16 * 16,
+            _ => 0x9000u16.wrapping_add_signed(self.tile_number as i8 as i16 * 16),
+        };
+        tile_addr + self.tile_row as u16 * 2
+    }
 
-            i -= 1;
-            l += 1;
+    fn construct_pixel_data(&self) -> [PixelData; 8] {
+        let mut pixels = [PixelData {
+            data: 0,
+            pallette: self.pallette,
+            bg_priority: false,
+        }; 8];
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            // Bit 7 is the leftmost pixel, and the high byte holds bit 1 of
+            // the colour
+            let bit = 7 - i;
+            pixel.data = (((self.data_1 >> bit) & 1) << 1) | ((self.data_0 >> bit) & 1);
         }
-        return_data
+        pixels
     }
 }
 
@@ -389,6 +520,8 @@ enum FetchMode {
 pub struct PixelData {
     data: u8,
     pallette: Pallette,
+    // Sprite pixels only: drawn behind background colours 1-3
+    bg_priority: bool,
 }
 
 impl PixelData {
@@ -409,7 +542,7 @@ fn create_fifo() -> PixelFIFO {
     use std::sync::mpsc::channel;
 
     let (request_sender, _) = channel();
-    PixelFIFO::new(request_sender)
+    PixelFIFO::new(Bus::new(request_sender))
 }
 
 #[test]
@@ -434,13 +567,11 @@ fn test_get_window_addr() {
     let mut addr = fifo.get_current_window_addr();
     assert!(addr == 0x9800, "{addr:x} is not 0x9800");
 
-    fifo.x = 32;
-    fifo.y = 64;
-
-    fifo.set_window_pos((24, 16));
+    fifo.fetcher.x = 8;
+    fifo.window_line = 48;
 
     addr = fifo.get_current_window_addr();
-    assert!(addr == 0x9879, "0x{addr:x} is not 0x9879");
+    assert!(addr == 0x98C1, "0x{addr:x} is not 0x98C1");
 }
 
 #[test]
@@ -450,5 +581,269 @@ fn test_get_srpite_addr() {
     let sprite = Sprite::from_bytes(0x10, 0x8, 0x30, 0b10000000);
 
     let addr = fifo.get_current_sprite_addr(sprite);
-    assert!(addr == 0x8030, "0x{addr:x} is not 0x8030");
+    assert!(addr == 0x8300, "0x{addr:x} is not 0x8300");
+
+    // Row 3 of an 8x16 sprite, and flipped
+    fifo.y = 3;
+    fifo.set_obj_height(16);
+    let sprite = Sprite::from_bytes(0x10, 0x8, 0x31, 0b00000000);
+    let addr = fifo.get_current_sprite_addr(sprite);
+    assert!(addr == 0x8306, "0x{addr:x} is not 0x8306");
+    let sprite = Sprite::from_bytes(0x10, 0x8, 0x31, 0b01000000);
+    let addr = fifo.get_current_sprite_addr(sprite);
+    assert!(addr == 0x8318, "0x{addr:x} is not 0x8318");
+}
+
+#[cfg(test)]
+use crate::cpu::memory_interface::DirectBus;
+
+#[cfg(test)]
+fn create_fifo_with_vram(vram: &[(u16, u8)]) -> PixelFIFO<DirectBus> {
+    let mut fifo = PixelFIFO::new(DirectBus::with_writes(vram));
+    fifo.set_pallettes(PalletteCollection {
+        background_pallette: Pallette::from_byte(PalletteName::Background, 0b11_10_01_00),
+        // Colour 1 is shade 3 with OBP0 and shade 2 with OBP1
+        sprite_pallette_01: Pallette::from_byte(PalletteName::Sprite01, 0b00_00_11_00),
+        sprite_pallette_02: Pallette::from_byte(PalletteName::Sprite02, 0b00_00_10_00),
+    });
+    fifo.set_bg_tile_map_addr(0x9800);
+    fifo
+}
+
+#[cfg(test)]
+fn render_line(fifo: &mut PixelFIFO<DirectBus>) -> ([[u8; 4]; 160], u16) {
+    let mut line = [[0; 4]; 160];
+    let mut dots = 0;
+    fifo.clear();
+    while fifo.x < 160 {
+        line = fifo.step(line).unwrap();
+        dots += 1;
+        assert!(dots < 456, "line took more than 456 dots");
+    }
+    (line, dots)
+}
+
+#[test]
+fn test_fetch_background() {
+    // Tile 1 row 0: left half colour 3, right half colour 2
+    let mut fifo = create_fifo_with_vram(&[(0x9800, 0x01), (0x8010, 0xF0), (0x8011, 0xFF)]);
+    fifo.set_window_bg_tile_data_area_addr(0x8000);
+
+    let (line, _) = render_line(&mut fifo);
+    let pallette = Pallette::from_byte(PalletteName::Background, 0b11_10_01_00);
+    assert_eq!(line[0], pallette.return_color(3));
+    assert_eq!(line[3], pallette.return_color(3));
+    assert_eq!(line[4], pallette.return_color(2));
+    assert_eq!(line[7], pallette.return_color(2));
+    assert_eq!(line[8], pallette.return_color(0));
+}
+
+#[test]
+fn test_fetch_signed_tile_data() {
+    // Tile -1 (0x8FF0) row 1: colour 1 on the leftmost pixel, in the second
+    // tile of the second map row
+    let mut fifo = create_fifo_with_vram(&[(0x9821, 0xFF), (0x8FF2, 0x80), (0x8FF3, 0x00)]);
+    fifo.set_window_bg_tile_data_area_addr(0x8800);
+    fifo.set_scroll((0, 9));
+
+    let (line, _) = render_line(&mut fifo);
+    let pallette = Pallette::from_byte(PalletteName::Background, 0b11_10_01_00);
+    assert_eq!(line[7], pallette.return_color(0));
+    assert_eq!(line[8], pallette.return_color(1));
+    assert_eq!(line[9], pallette.return_color(0));
+}
+
+#[test]
+fn test_fine_scroll() {
+    // Tile 0 row 0 is colour 1 on pixel 5 only
+    let mut fifo = create_fifo_with_vram(&[(0x8000, 0x04)]);
+    fifo.set_window_bg_tile_data_area_addr(0x8000);
+
+    let (_, dots) = render_line(&mut fifo);
+    assert_eq!(dots, 172);
+
+    fifo.set_scroll((5, 0));
+    let (line, dots) = render_line(&mut fifo);
+    let pallette = Pallette::from_byte(PalletteName::Background, 0b11_10_01_00);
+    assert_eq!(line[0], pallette.return_color(1));
+    assert_eq!(line[1], pallette.return_color(0));
+    // Every 8th pixel after that is the same tile column again
+    assert_eq!(line[8], pallette.return_color(1));
+    assert_eq!(dots, 177);
+}
+
+#[test]
+fn test_sprite_penalty() {
+    let mut fifo = create_fifo_with_vram(&[]);
+    fifo.set_window_bg_tile_data_area_addr(0x8000);
+
+    // Two sprites at the start of the same tile: the first waits out the
+    // background fetch, the second only pays for its own fetch
+    let mut sprites = [None; 10];
+    sprites[0] = Some(Sprite::from_bytes(0x10, 0x18, 0, 0));
+    sprites[1] = Some(Sprite::from_bytes(0x10, 0x19, 0, 0));
+    fifo.set_sprites(sprites);
+    let (_, dots) = render_line(&mut fifo);
+    assert_eq!(dots, 172 + 11 + 6);
+}
+
+#[cfg(test)]
+fn create_window_fifo(window_pos: (u8, u8)) -> PixelFIFO<DirectBus> {
+    // Background is colour 0; the window uses tile 1 from the 0x9C00 map,
+    // whose row 0 is colour 1 and row 1 colour 3
+    let mut fifo = create_fifo_with_vram(&[
+        (0x9C00, 0x01),
+        (0x8010, 0xFF),
+        (0x8012, 0xFF),
+        (0x8013, 0xFF),
+    ]);
+    fifo.set_window_bg_tile_data_area_addr(0x8000);
+    fifo.set_window_tile_map_addr(0x9C00);
+    fifo.set_window_enable(true);
+    fifo.set_window_pos(window_pos);
+    fifo
+}
+
+#[test]
+fn test_window_position() {
+    let mut fifo = create_window_fifo((7 + 16, 1));
+    let pallette = Pallette::from_byte(PalletteName::Background, 0b11_10_01_00);
+
+    // Not drawn above WY
+    let (line, _) = render_line(&mut fifo);
+    assert_eq!(line[16], pallette.return_color(0));
+    fifo.inc_y();
+
+    // WX - 7 is the left edge
+    let (line, dots) = render_line(&mut fifo);
+    assert_eq!(line[15], pallette.return_color(0));
+    assert_eq!(line[16], pallette.return_color(1));
+    assert_eq!(line[24], pallette.return_color(0));
+    // Restarting the fetcher for the window costs 6 dots
+    assert_eq!(dots, 172 + 6);
+    fifo.inc_y();
+
+    // WY stays latched for the rest of the frame
+    fifo.set_window_pos((7 + 16, 100));
+    let (line, _) = render_line(&mut fifo);
+    assert_eq!(line[16], pallette.return_color(3));
+}
+
+#[test]
+fn test_window_line_counter() {
+    let mut fifo = create_window_fifo((7, 0));
+    let pallette = Pallette::from_byte(PalletteName::Background, 0b11_10_01_00);
+
+    // Window row 0
+    let (line, _) = render_line(&mut fifo);
+    assert_eq!(line[0], pallette.return_color(1));
+    fifo.inc_y();
+
+    // Disabled for a line, which doesn't count towards the window's lines
+    fifo.set_window_enable(false);
+    render_line(&mut fifo);
+    fifo.inc_y();
+
+    // Window row 1 on LY 2
+    fifo.set_window_enable(true);
+    let (line, _) = render_line(&mut fifo);
+    assert_eq!(line[0], pallette.return_color(3));
+    fifo.inc_y();
+
+    // Starts over the next frame
+    fifo.reset_y();
+    let (line, _) = render_line(&mut fifo);
+    assert_eq!(line[0], pallette.return_color(1));
+}
+
+#[test]
+fn test_sprite_rendering() {
+    // Tile 2 row 0 is colour 1 on its two leftmost pixels
+    let mut fifo = create_fifo_with_vram(&[(0x8020, 0xC0)]);
+    fifo.set_window_bg_tile_data_area_addr(0x8000);
+    let shade = Pallette::from_byte(PalletteName::Background, 0b11_10_01_00);
+
+    let mut sprites = [None; 10];
+    // OBP0 at x = 8
+    sprites[0] = Some(Sprite::from_bytes(0x10, 0x10, 2, 0b0000_0000));
+    // OBP1 and flipped, half off the left edge at x = -4
+    sprites[1] = Some(Sprite::from_bytes(0x10, 0x04, 2, 0b0011_0000));
+    fifo.set_sprites(sprites);
+    let (line, _) = render_line(&mut fifo);
+    assert_eq!(line[1], shade.return_color(0));
+    assert_eq!(line[2], shade.return_color(2));
+    assert_eq!(line[3], shade.return_color(2));
+    assert_eq!(line[4], shade.return_color(0));
+    assert_eq!(line[8], shade.return_color(3));
+    assert_eq!(line[9], shade.return_color(3));
+    assert_eq!(line[10], shade.return_color(0));
+}
+
+#[test]
+fn test_sprite_priority() {
+    // Background tile 1 is colour 1 on the left half of the first tile. Sprite
+    // tile 2 is colour 1 across the row, tile 3 on the right half only
+    let mut fifo = create_fifo_with_vram(&[
+        (0x9800, 0x01),
+        (0x8010, 0xF0),
+        (0x8020, 0xFF),
+        (0x8030, 0x0F),
+    ]);
+    fifo.set_window_bg_tile_data_area_addr(0x8000);
+    let shade = Pallette::from_byte(PalletteName::Background, 0b11_10_01_00);
+
+    let mut sprites = [None; 10];
+    // Behind background colours 1-3 at x = 0
+    sprites[0] = Some(Sprite::from_bytes(0x10, 0x08, 2, 0b1000_0000));
+    // Same X: the lower OAM index wins where both are opaque
+    sprites[1] = Some(Sprite::from_bytes(0x10, 0x18, 3, 0b0000_0000));
+    sprites[2] = Some(Sprite::from_bytes(0x10, 0x18, 2, 0b0001_0000));
+    // Lower X wins regardless of OAM index
+    sprites[3] = Some(Sprite::from_bytes(0x10, 0x28, 2, 0b0001_0000));
+    sprites[4] = Some(Sprite::from_bytes(0x10, 0x24, 2, 0b0000_0000));
+    fifo.set_sprites(sprites);
+    let (line, _) = render_line(&mut fifo);
+
+    assert_eq!(line[3], shade.return_color(1));
+    assert_eq!(line[4], shade.return_color(3));
+    assert_eq!(line[19], shade.return_color(2));
+    assert_eq!(line[20], shade.return_color(3));
+    assert_eq!(line[35], shade.return_color(3));
+    assert_eq!(line[36], shade.return_color(2));
+
+    // Partly off the left edge the lower X still wins, although both sprites
+    // start at pixel 0
+    let mut sprites = [None; 10];
+    sprites[0] = Some(Sprite::from_bytes(0x10, 0x06, 2, 0b0001_0000));
+    sprites[1] = Some(Sprite::from_bytes(0x10, 0x03, 2, 0b0000_0000));
+    fifo.set_sprites(sprites);
+    let (line, _) = render_line(&mut fifo);
+
+    assert_eq!(line[2], shade.return_color(3));
+    assert_eq!(line[3], shade.return_color(2));
+    assert_eq!(line[5], shade.return_color(2));
+    assert_eq!(line[6], shade.return_color(0));
+}
+
+#[test]
+fn test_bg_window_disable() {
+    // Background tile 1 is colour 1 on the left half of the first tile, under
+    // a sprite from x = 2 that has the priority flag set
+    let mut fifo = create_fifo_with_vram(&[(0x9800, 0x01), (0x8010, 0xF0), (0x8020, 0xFF)]);
+    fifo.set_window_bg_tile_data_area_addr(0x8000);
+    let shade = Pallette::from_byte(PalletteName::Background, 0b11_10_01_00);
+    let mut sprites = [None; 10];
+    sprites[0] = Some(Sprite::from_bytes(0x10, 0x0A, 2, 0b1000_0000));
+
+    fifo.set_sprites(sprites);
+    let (line, _) = render_line(&mut fifo);
+    assert_eq!(line[0], shade.return_color(1));
+    assert_eq!(line[2], shade.return_color(1));
+
+    // LCDC bit 0 clear: the background is colour 0 and the sprite is on top
+    fifo.set_bg_window_enable(false);
+    fifo.set_sprites(sprites);
+    let (line, _) = render_line(&mut fifo);
+    assert_eq!(line[0], shade.return_color(0));
+    assert_eq!(line[2], shade.return_color(3));
 }
@@ -26,9 +26,10 @@ impl Sprite {
         }
     }
 
-    pub fn is_visible(&self, current_line: u8) -> bool {
-        current_line + 16 >= self.y_coordinate && current_line + 16 < self.y_coordinate + 8
-        // todo!()
+    // `height` is 8, or 16 for 8x16 sprites
+    pub fn is_visible(&self, current_line: u8, height: u8) -> bool {
+        let line = current_line as u16 + 16;
+        line >= self.y_coordinate as u16 && line < self.y_coordinate as u16 + height as u16
     }
 }
 
@@ -0,0 +1,85 @@
+/*
+
+Interrupts
+
+0xFF0F: IF - Interrupt Flag (requested interrupts)
+0xFFFF: IE - Interrupt Enable
+
+Bit 0: VBlank  -> 0x40
+Bit 1: LCD STAT -> 0x48
+Bit 2: Timer   -> 0x50
+Bit 3: Serial  -> 0x58
+Bit 4: Joypad  -> 0x60
+
+Lower bits have higher priority.
+
+*/
+
+pub const IF_ADDR: u16 = 0xFF0F;
+pub const IE_ADDR: u16 = 0xFFFF;
+
+// Servicing an interrupt takes 5 m-cycles: 2 wait states, 2 to push PC and 1
+// to set PC to the vector
+pub const DISPATCH_T: u8 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptFlag {
+    VBlank,
+    LCDStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl InterruptFlag {
+    const PRIORITY: [InterruptFlag; 5] = [
+        InterruptFlag::VBlank,
+        InterruptFlag::LCDStat,
+        InterruptFlag::Timer,
+        InterruptFlag::Serial,
+        InterruptFlag::Joypad,
+    ];
+
+    pub fn bit(&self) -> u8 {
+        match self {
+            InterruptFlag::VBlank => 1,
+            InterruptFlag::LCDStat => 1 << 1,
+            InterruptFlag::Timer => 1 << 2,
+            InterruptFlag::Serial => 1 << 3,
+            InterruptFlag::Joypad => 1 << 4,
+        }
+    }
+
+    pub fn vector(&self) -> u16 {
+        match self {
+            InterruptFlag::VBlank => 0x40,
+            InterruptFlag::LCDStat => 0x48,
+            InterruptFlag::Timer => 0x50,
+            InterruptFlag::Serial => 0x58,
+            InterruptFlag::Joypad => 0x60,
+        }
+    }
+
+    // Takes IE & IF and returns the highest priority interrupt that is both
+    // requested and enabled
+    pub fn from_pending(pending: u8) -> Option<InterruptFlag> {
+        InterruptFlag::PRIORITY
+            .into_iter()
+            .find(|flag| pending & flag.bit() != 0)
+    }
+}
+
+#[test]
+fn test_from_pending() {
+    assert_eq!(InterruptFlag::from_pending(0), None);
+    assert_eq!(InterruptFlag::from_pending(0b11100000), None);
+    assert_eq!(
+        InterruptFlag::from_pending(0b00010100),
+        Some(InterruptFlag::Timer)
+    );
+    assert_eq!(
+        InterruptFlag::from_pending(0b00011111),
+        Some(InterruptFlag::VBlank)
+    );
+    assert_eq!(InterruptFlag::Joypad.vector(), 0x60);
+}
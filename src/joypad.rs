@@ -0,0 +1,151 @@
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+/*
+
+Joypad (P1, 0xFF00)
+
+Bit 5: Select action buttons (0=Select)
+Bit 4: Select direction buttons (0=Select)
+Bit 3: Down  / Start  (0=Pressed)
+Bit 2: Up    / Select (0=Pressed)
+Bit 1: Left  / B      (0=Pressed)
+Bit 0: Right / A      (0=Pressed)
+
+The buttons form a 2x4 matrix; the lower nibble reads the lines of whichever
+row(s) the game selected. A line going from high to low requests the joypad
+interrupt.
+
+*/
+
+pub const JOYP_ADDR: u16 = 0xFF00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    // Bit in `Joypad::pressed`; the lower nibble holds the direction row and
+    // the upper nibble the action row
+    fn bit(&self) -> u8 {
+        match self {
+            Button::Right => 1,
+            Button::Left => 1 << 1,
+            Button::Up => 1 << 2,
+            Button::Down => 1 << 3,
+            Button::A => 1 << 4,
+            Button::B => 1 << 5,
+            Button::Select => 1 << 6,
+            Button::Start => 1 << 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoypadEvent {
+    Pressed(Button),
+    Released(Button),
+}
+
+#[derive(Debug)]
+pub struct Joypad {
+    pressed: u8,
+    // Bits 4 and 5 of P1, as written by the game
+    select: u8,
+    receiver: Receiver<JoypadEvent>,
+}
+
+impl Joypad {
+    pub fn new(receiver: Receiver<JoypadEvent>) -> Self {
+        Joypad {
+            pressed: 0,
+            select: 0x30,
+            receiver,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    // Returns true if the joypad interrupt should be requested
+    pub fn write(&mut self, value: u8) -> bool {
+        let lines = self.lines();
+        self.select = value & 0x30;
+        Joypad::falling_edge(lines, self.lines())
+    }
+
+    // Applies the key events sent since the last update; returns true if the
+    // joypad interrupt should be requested
+    pub fn update(&mut self) -> bool {
+        let lines = self.lines();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(JoypadEvent::Pressed(button)) => self.pressed |= button.bit(),
+                Ok(JoypadEvent::Released(button)) => self.pressed &= !button.bit(),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        Joypad::falling_edge(lines, self.lines())
+    }
+
+    fn lines(&self) -> u8 {
+        let mut lines = 0x0F;
+        if self.select & 0x10 == 0 {
+            lines &= !(self.pressed & 0x0F);
+        }
+        if self.select & 0x20 == 0 {
+            lines &= !(self.pressed >> 4);
+        }
+        lines
+    }
+
+    fn falling_edge(old: u8, new: u8) -> bool {
+        old & !new != 0
+    }
+}
+
+#[cfg(test)]
+use std::sync::mpsc::channel;
+
+#[test]
+fn test_joypad_matrix() {
+    let (sender, receiver) = channel();
+    let mut joypad = Joypad::new(receiver);
+
+    sender.send(JoypadEvent::Pressed(Button::Start)).unwrap();
+    sender.send(JoypadEvent::Pressed(Button::Left)).unwrap();
+    // Neither row selected, no line goes low
+    assert!(!joypad.update());
+    assert_eq!(joypad.read(), 0xFF);
+
+    // Selecting the direction row brings Left low
+    assert!(joypad.write(0x20));
+    assert_eq!(joypad.read(), 0xED);
+
+    assert!(joypad.write(0x10));
+    assert_eq!(joypad.read(), 0xD7);
+
+    sender.send(JoypadEvent::Released(Button::Start)).unwrap();
+    assert!(!joypad.update());
+    assert_eq!(joypad.read(), 0xDF);
+}
+
+#[test]
+fn test_joypad_interrupt_on_press() {
+    let (sender, receiver) = channel();
+    let mut joypad = Joypad::new(receiver);
+    joypad.write(0x00);
+
+    sender.send(JoypadEvent::Pressed(Button::A)).unwrap();
+    assert!(joypad.update());
+    // Holding the button doesn't request it again
+    assert!(!joypad.update());
+}
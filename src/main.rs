@@ -1,17 +1,36 @@
+pub mod apu;
+pub mod args;
+pub mod audio;
+pub mod bindings;
+pub mod cartridge;
 pub mod cpu;
+pub mod dma;
+pub mod gameboy;
 pub mod gpu;
+pub mod interrupt;
+pub mod joypad;
 pub mod request_response;
+pub mod timer;
+use apu::apu::DEFAULT_SAMPLE_RATE;
+use args::{Args, USAGE};
+use bindings::{Action, Bindings, Hotkey};
+use cartridge::header::CartridgeHeader;
 use cpu::cpu::CPU;
-use cpu::memory_bus::MemoryBus;
+use cpu::memory_bus::{MemoryBus, ROM_DIR};
+use gameboy::{Frame, GameBoy, FRAME_T};
 use gpu::gpu::GPU;
 use gpu::tile::Color;
-use request_response::Request;
-// use std::env;
-// use std::time::Instant;
+use joypad::JoypadEvent;
+use request_response::{Bus, Request};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{self, channel, Receiver, Sender};
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use winit::dpi::LogicalSize;
-use winit::event::VirtualKeyCode;
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::{Fullscreen, WindowBuilder};
 use winit_input_helper::WinitInputHelper;
@@ -80,54 +99,308 @@ use gpu::lcd::LCD;
 //     }
 // }
 
-fn main() {
-    let (request_sender, request_receiver) = channel::<Request>();
-    // Create Memory Thread
-    thread::spawn(move || {
-        let mut memory = MemoryBus::new(request_receiver, String::from("hello-world.gb"));
-        loop {
-            memory.step();
+// Time the hardware takes to run a frame
+const FRAME_DURATION: Duration = Duration::from_nanos(FRAME_T as u64 * 1_000_000_000 / 4_194_304);
+
+// Sample batches the memory thread can get ahead of the audio thread by
+const AUDIO_QUEUE: usize = 2;
+
+// How often the bindings file is checked for changes
+const BINDINGS_POLL: Duration = Duration::from_secs(1);
+
+fn load_bindings(path: &Path) -> Option<Bindings> {
+    match Bindings::load(path) {
+        Ok(bindings) => Some(bindings),
+        Err(errors) => {
+            for error in errors {
+                println!("Warning: {}: {error}", path.display());
+            }
+            None
         }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// File named after the ROM and the current time, for screenshots and
+// recordings started from hotkeys
+fn timestamped_path(rom_name: &str, extension: &str) -> PathBuf {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let stem = Path::new(rom_name)
+        .file_stem()
+        .map_or(String::from("gb"), |stem| {
+            stem.to_string_lossy().into_owned()
+        });
+    PathBuf::from(format!("{stem}-{secs}.{extension}"))
+}
+
+// Holds frames back to real time. Not needed with a real-time audio sink,
+// which paces emulation by blocking, or while fast-forwarding
+fn limit_frame(frame_start: &mut Instant, fast_forward: &AtomicBool, audio_paced: bool) {
+    let elapsed = frame_start.elapsed();
+    if !audio_paced && !fast_forward.load(Ordering::Relaxed) && elapsed < FRAME_DURATION {
+        thread::sleep(FRAME_DURATION - elapsed);
+    }
+    *frame_start = Instant::now();
+}
+
+// Runs the memory bus, CPU and PPU on a thread each, the CPU and PPU kept in
+// lockstep by exchanging t-cycle counts
+#[allow(clippy::too_many_arguments)]
+fn spawn_processing_units(
+    mut memory: MemoryBus,
+    request_sender: Sender<Request>,
+    lcd_sender: Sender<Frame>,
+    skip_boot: bool,
+    fault_sender: Sender<String>,
+    paused: Arc<AtomicBool>,
+    fast_forward: Arc<AtomicBool>,
+    audio_paced: bool,
+) {
+    // Create Memory Thread
+    thread::spawn(move || loop {
+        memory.step();
     });
     let (ppu_timing_sender, cpu_timing_receiver) = channel::<u8>();
     let (cpu_timing_sender, ppu_timing_receiver) = channel::<u8>();
     // Create CPU thread
     let cpu_request_sender = request_sender.clone();
+    let cpu_fault_sender = fault_sender.clone();
     thread::spawn(move || {
-        let mut cpu = CPU::new(cpu_request_sender);
+        let mut cpu = CPU::new(Bus::new(cpu_request_sender));
+        if skip_boot {
+            cpu.skip_boot();
+        }
         let mut relative_t = 0;
         loop {
+            // The PPU blocks waiting on the CPU, so this pauses both
+            if paused.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
             if relative_t <= 0 {
-                let step_t = cpu.step();
+                let step_t = match cpu.step() {
+                    Ok(t) => t,
+                    Err(err) => {
+                        let pc = cpu.pc;
+                        let _ =
+                            cpu_fault_sender.send(format!("CPU stopped at PC {pc:#06X}: {err}"));
+                        return;
+                    }
+                };
                 relative_t += step_t as i32;
-                cpu_timing_sender.send(step_t).unwrap();
+                // A closed channel means the PPU has stopped
+                if cpu_timing_sender.send(step_t).is_err() {
+                    return;
+                }
             } else {
                 relative_t -= match cpu_timing_receiver.recv() {
                     Ok(x) => x as i32,
-                    Err(e) => panic!("{e:}"),
+                    Err(_) => return,
                 }
             }
             // let relative_t = cpu.step();
         }
     });
-    let (lcd_sender, lcd_receiver) = mpsc::channel::<[[[u8; 4]; 160]; 144]>();
     // Create PPU thread
     thread::spawn(move || {
-        let mut ppu = GPU::new(request_sender, lcd_sender);
+        let mut ppu = GPU::new(Bus::new(request_sender), lcd_sender);
         let mut relative_t = 0;
+        let mut frame_t = 0;
+        let mut frame_start = Instant::now();
         loop {
             if relative_t <= 0 {
-                let step_t = ppu.step();
+                let step_t = match ppu.step() {
+                    Ok(t) => t,
+                    Err(err) => {
+                        let line = ppu.line;
+                        let _ = fault_sender.send(format!("PPU stopped on line {line}: {err}"));
+                        return;
+                    }
+                };
                 relative_t += step_t as i32;
-                ppu_timing_sender.send(step_t).unwrap();
+                // A closed channel means the CPU has stopped
+                if ppu_timing_sender.send(step_t).is_err() {
+                    return;
+                }
+
+                frame_t += step_t as u32;
+                if frame_t >= FRAME_T {
+                    frame_t -= FRAME_T;
+                    limit_frame(&mut frame_start, &fast_forward, audio_paced);
+                }
             } else {
                 relative_t -= match ppu_timing_receiver.recv() {
                     Ok(x) => x as i32,
-                    Err(e) => panic!("{e:}"),
+                    Err(_) => return,
                 }
             }
         }
     });
+}
+
+// Runs everything on one thread through `GameBoy`
+fn spawn_gameboy(
+    memory: MemoryBus,
+    lcd_sender: Sender<Frame>,
+    skip_boot: bool,
+    fault_sender: Sender<String>,
+    paused: Arc<AtomicBool>,
+    fast_forward: Arc<AtomicBool>,
+    audio_paced: bool,
+) {
+    thread::spawn(move || {
+        let mut gameboy = GameBoy::new(memory, lcd_sender, skip_boot);
+        let mut frame_start = Instant::now();
+        loop {
+            gameboy.serve_pending();
+            if paused.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+            if let Err(err) = gameboy.run_frame() {
+                let pc = gameboy.pc();
+                let _ = fault_sender.send(format!("Stopped at PC {pc:#06X}: {err}"));
+                break;
+            }
+            limit_frame(&mut frame_start, &fast_forward, audio_paced);
+        }
+        // The memory bus lives on this thread, so keep serving the event loop
+        // for it to save on the way out
+        loop {
+            gameboy.serve_pending();
+            thread::sleep(Duration::from_millis(10));
+        }
+    });
+}
+
+fn print_info(rom_name: &str) {
+    let rom = match fs::read(Path::new(ROM_DIR).join(rom_name)) {
+        Ok(rom) => rom,
+        Err(err) => {
+            println!("Could not read {rom_name}: {err:}");
+            return;
+        }
+    };
+    match CartridgeHeader::from_rom(&rom) {
+        Ok(header) => {
+            println!("{header}");
+            let warnings = header.validate(&rom);
+            if warnings.is_empty() {
+                println!("Header OK");
+            }
+            for warning in warnings {
+                println!("Warning: {warning}");
+            }
+        }
+        Err(err) => println!("{rom_name}: {err}"),
+    }
+}
+
+fn main() {
+    let args = match Args::parse(env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(err) => {
+            println!("{err}\n\n{USAGE}");
+            return;
+        }
+    };
+    if args.info {
+        print_info(&args.rom_name);
+        return;
+    }
+
+    let boot_rom = match args.skip_boot {
+        true => None,
+        false => match MemoryBus::read_boot_rom() {
+            Ok(boot_rom) => Some(boot_rom),
+            Err(err) => {
+                println!("Boot ROM not found ({err}), skipping boot");
+                None
+            }
+        },
+    };
+    let skip_boot = boot_rom.is_none();
+
+    let bindings_path = PathBuf::from(&args.bindings_path);
+    let mut bindings = load_bindings(&bindings_path).unwrap_or_default();
+    let mut bindings_modified = modified_time(&bindings_path);
+    let mut bindings_checked = Instant::now();
+    let rom_name = args.rom_name.clone();
+
+    let paused = Arc::new(AtomicBool::new(false));
+    let fast_forward = Arc::new(AtomicBool::new(false));
+
+    let (audio_sender, audio_receiver) = mpsc::sync_channel::<Vec<[f32; 2]>>(AUDIO_QUEUE);
+    let (paced_sender, paced_receiver) = channel::<bool>();
+    // Create audio thread. The sink is opened here as device streams can't
+    // move between threads; real-time sinks take over pacing from the PPU
+    let audio_fast_forward = fast_forward.clone();
+    let (audio_backend, sample_rate) = (args.audio.clone(), args.sample_rate);
+    thread::spawn(move || {
+        let sink = audio::output::open_sink(&audio_backend, sample_rate);
+        paced_sender.send(sink.buffer_level().is_some()).unwrap();
+        audio::output::run(
+            audio_receiver,
+            sink,
+            DEFAULT_SAMPLE_RATE,
+            audio_fast_forward,
+        );
+    });
+    let audio_paced = paced_receiver.recv().unwrap();
+
+    let (request_sender, request_receiver) = channel::<Request>();
+    let (joypad_sender, joypad_receiver) = channel::<JoypadEvent>();
+    let mut memory = MemoryBus::new(
+        request_receiver,
+        args.rom_name,
+        boot_rom,
+        joypad_receiver,
+        Some(audio_sender),
+        args.sample_rate,
+    );
+    // Started before the CPU runs, so the recording begins at power on
+    let mut recording = false;
+    let record_stems = args.record_stems;
+    if let Some(path) = &args.record {
+        match memory.start_recording(path, record_stems) {
+            Ok(()) => recording = true,
+            Err(err) => println!("Warning: could not record: {err}"),
+        }
+    }
+    // For requests from the event loop
+    let control_bus = Bus::new(request_sender.clone());
+    // The emulation threads report the bus error they stopped on here
+    let (fault_sender, fault_receiver) = channel::<String>();
+    let (lcd_sender, lcd_receiver) = mpsc::channel::<Frame>();
+    if args.single_thread {
+        spawn_gameboy(
+            memory,
+            lcd_sender,
+            skip_boot,
+            fault_sender,
+            paused.clone(),
+            fast_forward.clone(),
+            audio_paced,
+        );
+    } else {
+        spawn_processing_units(
+            memory,
+            request_sender,
+            lcd_sender,
+            skip_boot,
+            fault_sender,
+            paused.clone(),
+            fast_forward.clone(),
+            audio_paced,
+        );
+    }
     // Create LCD thread
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
@@ -141,10 +414,37 @@ fn main() {
     let mut lcd = LCD::new(&window, lcd_receiver);
     lcd.render();
 
+    #[cfg(feature = "gamepad")]
+    let mut gilrs = match gilrs::Gilrs::new() {
+        Ok(gilrs) => Some(gilrs),
+        Err(err) => {
+            println!("Warning: gamepad support unavailable: {err:}");
+            None
+        }
+    };
+    #[cfg(feature = "gamepad")]
+    let mut axis_state = bindings::AxisState::default();
+
     event_loop.run(move |event, _, control_flow| {
         if input.update(&event) {
+            // A processing unit stopping on a bus error shuts down like a
+            // close event
+            let fault = fault_receiver.try_recv().ok();
+            if let Some(fault) = &fault {
+                println!("Error: {fault}");
+            }
+
             // Close event
-            if input.quit() {
+            if input.quit() || fault.is_some() {
+                // Flush battery RAM before the memory thread is torn down
+                if recording {
+                    if let Err(err) = control_bus.try_stop_recording() {
+                        println!("Warning: could not stop recording: {err}");
+                    }
+                }
+                if let Err(err) = control_bus.try_save() {
+                    println!("Warning: could not save: {err}");
+                }
                 *control_flow = ControlFlow::Exit;
                 return;
             }
@@ -154,14 +454,108 @@ fn main() {
                 lcd.resize(size);
             }
 
-            // Fullscreen
-            if input.key_pressed(VirtualKeyCode::F11) {
-                match window.fullscreen() {
-                    None => {
-                        window
-                            .set_fullscreen(Some(Fullscreen::Borderless(window.current_monitor())));
+            // Pick up edits to the bindings file; a broken file keeps the
+            // previous bindings
+            if bindings_checked.elapsed() >= BINDINGS_POLL {
+                bindings_checked = Instant::now();
+                let modified = modified_time(&bindings_path);
+                if modified != bindings_modified {
+                    bindings_modified = modified;
+                    if let Some(reloaded) = load_bindings(&bindings_path) {
+                        println!("Reloaded {}", bindings_path.display());
+                        bindings = reloaded;
+                    }
+                }
+            }
+
+            // (action, pressed) for every input that changed this frame
+            let mut actions = vec![];
+            for (key, action) in bindings.keys() {
+                if input.key_pressed(key) {
+                    actions.push((action, true));
+                }
+                if input.key_released(key) {
+                    actions.push((action, false));
+                }
+            }
+
+            #[cfg(feature = "gamepad")]
+            if let Some(gilrs) = &mut gilrs {
+                use bindings::{GamepadAxis, GamepadButton, GamepadInput};
+                use gilrs::EventType;
+
+                while let Some(gilrs::Event { event, .. }) = gilrs.next_event() {
+                    let inputs = match event {
+                        EventType::ButtonPressed(button, _) => GamepadButton::from_gilrs(button)
+                            .map(|button| vec![(GamepadInput::Button(button), true)])
+                            .unwrap_or_default(),
+                        EventType::ButtonReleased(button, _) => GamepadButton::from_gilrs(button)
+                            .map(|button| vec![(GamepadInput::Button(button), false)])
+                            .unwrap_or_default(),
+                        EventType::AxisChanged(axis, value, _) => GamepadAxis::from_gilrs(axis)
+                            .map(|axis| axis_state.update(axis, value))
+                            .unwrap_or_default(),
+                        _ => vec![],
+                    };
+                    for (input, pressed) in inputs {
+                        if let Some(action) = bindings.gamepad_action(input) {
+                            actions.push((action, pressed));
+                        }
+                    }
+                }
+            }
+
+            for (action, pressed) in actions {
+                match (action, pressed) {
+                    (Action::Joypad(button), true) => {
+                        joypad_sender.send(JoypadEvent::Pressed(button)).unwrap()
+                    }
+                    (Action::Joypad(button), false) => {
+                        joypad_sender.send(JoypadEvent::Released(button)).unwrap()
+                    }
+                    // Fast-forward is held, the other hotkeys act on press
+                    (Action::Hotkey(Hotkey::FastForward), _) => {
+                        fast_forward.store(pressed, Ordering::Relaxed)
+                    }
+                    (_, false) => {}
+                    (Action::Hotkey(Hotkey::Pause), true) => {
+                        let was_paused = paused.fetch_xor(true, Ordering::Relaxed);
+                        println!("{}", if was_paused { "Resumed" } else { "Paused" });
+                    }
+                    (Action::Hotkey(Hotkey::Screenshot), true) => {
+                        let path = timestamped_path(&rom_name, "ppm");
+                        match lcd.screenshot(&path) {
+                            Ok(()) => println!("Saved screenshot to {}", path.display()),
+                            Err(err) => {
+                                println!("Warning: could not write {}: {err:}", path.display())
+                            }
+                        }
                     }
-                    Some(_) => window.set_fullscreen(None),
+                    (Action::Hotkey(Hotkey::Fullscreen), true) => match window.fullscreen() {
+                        None => {
+                            window.set_fullscreen(Some(Fullscreen::Borderless(
+                                window.current_monitor(),
+                            )));
+                        }
+                        Some(_) => window.set_fullscreen(None),
+                    },
+                    (Action::Hotkey(Hotkey::Record), true) => match recording {
+                        true => {
+                            control_bus.stop_recording();
+                            recording = false;
+                            println!("Stopped recording");
+                        }
+                        false => {
+                            let path = timestamped_path(&rom_name, "wav");
+                            match control_bus.try_start_recording(path.clone(), record_stems) {
+                                Ok(()) => {
+                                    recording = true;
+                                    println!("Recording to {}", path.display());
+                                }
+                                Err(err) => println!("Warning: could not record: {err}"),
+                            }
+                        }
+                    },
                 }
             }
 
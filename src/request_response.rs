@@ -1,4 +1,10 @@
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::mpsc::{channel, Sender},
+};
+
+use crate::interrupt::InterruptFlag;
 
 #[derive(Debug)]
 pub struct Request {
@@ -6,85 +12,6 @@ pub struct Request {
     pub responder: Sender<Response>,
 }
 
-impl Request {
-    fn create_read_byte_request(addr: u16) -> (Self, Receiver<Response>) {
-        let (response_sender, response_receiver) = channel::<Response>();
-        let request_info = RequestInfo {
-            addr,
-            request_len: 1,
-            request_type: RequestType::Read,
-        };
-        return (
-            Request {
-                request_info,
-                responder: response_sender,
-            },
-            response_receiver,
-        );
-    }
-
-    fn create_read_word_request(addr: u16) -> (Self, Receiver<Response>) {
-        let (response_sender, response_receiver) = channel::<Response>();
-        let request_info = RequestInfo {
-            addr,
-            request_len: 2,
-            request_type: RequestType::Read,
-        };
-        return (
-            Request {
-                request_info,
-                responder: response_sender,
-            },
-            response_receiver,
-        );
-    }
-
-    fn create_write_byte_request(addr: u16, data: u8) -> (Self, Receiver<Response>) {
-        let (response_sender, response_receiver) = channel::<Response>();
-        let request_info = RequestInfo {
-            addr,
-            request_len: 1,
-            request_type: RequestType::Write(vec![data]),
-        };
-        return (
-            Request {
-                request_info,
-                responder: response_sender,
-            },
-            response_receiver,
-        );
-    }
-
-    fn create_read_oam_request() -> (Self, Receiver<Response>) {
-        let (response_sender, response_receiver) = channel::<Response>();
-        let request_info = RequestInfo {
-            addr: 0xFE00,
-            request_len: 160,
-            request_type: RequestType::Read,
-        };
-        return (
-            Request {
-                request_info,
-                responder: response_sender,
-            },
-            response_receiver,
-        );
-    }
-
-    // pub fn get_request_type(&self) -> RequestType {
-    //     match self.request_info.request_type {
-    //         RequestType::Read => {RequestType::Read},
-    //         RequestType::Write(data) => {
-    //             let mut new_data: Vec<u8> = vec![];
-    //             for x in data {
-    //                 new_data.push(x);
-    //             }
-    //             RequestType::Write(new_data)
-    //         }
-    //     }
-    // }
-}
-
 #[derive(Debug)]
 pub struct RequestInfo {
     pub addr: u16,
@@ -95,7 +22,16 @@ pub struct RequestInfo {
 pub enum RequestType {
     Read,
     Write(Vec<u8>),
-    LoadROM,
+    // Sets the given bit in IF (0xFF0F) without a read-modify-write round trip
+    Interrupt(InterruptFlag),
+    // Flushes battery-backed cartridge RAM to disk
+    Save,
+    // Advances the peripherals clocked by the CPU by the given t-cycles
+    Tick(u8),
+    // Records the APU output from this point to a WAV file, with per-channel
+    // stems if set
+    StartRecording(PathBuf, bool),
+    StopRecording,
 }
 
 pub enum Response {
@@ -105,89 +41,264 @@ pub enum Response {
     Ok204,
 }
 
-#[derive(Debug)]
-pub struct Bus {
-    pub request_sender: Sender<Request>,
+// A request the memory thread couldn't serve. Each variant carries the
+// address and a description of the request for reporting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BusError {
+    MemError {
+        addr: u16,
+        request: String,
+        err: String,
+    },
+    RequestError {
+        addr: u16,
+        request: String,
+        err: String,
+    },
+    // A 200 where a 204 was expected or the other way round
+    UnexpectedResponse {
+        addr: u16,
+        request: String,
+        response: String,
+    },
+    // The memory thread has shut down
+    Disconnected {
+        addr: u16,
+        request: String,
+    },
 }
 
-impl Bus {
-    pub fn read_byte(&self, addr: u16) -> u8 {
-        let (request, response_receiver) = Request::create_read_byte_request(addr);
-        self.request_sender.send(request).unwrap();
-        match response_receiver.recv() {
-            Ok(response) => match response {
-                Response::Ok200(data) => data[0],
-                Response::MemError(err) => panic!("{err:}"),
-                Response::RequestError(err) => panic!("{err:}"),
-                Response::Ok204 => panic!("Error, expected data, received 204"),
-            },
-            Err(err) => panic!("{err:}"),
+impl BusError {
+    pub fn addr(&self) -> u16 {
+        match self {
+            BusError::MemError { addr, .. }
+            | BusError::RequestError { addr, .. }
+            | BusError::UnexpectedResponse { addr, .. }
+            | BusError::Disconnected { addr, .. } => *addr,
         }
     }
+}
 
-    pub fn read_word(&self, addr: u16) -> u16 {
-        let (request, response_receiver) = Request::create_read_word_request(addr);
-        self.request_sender.send(request).unwrap();
-        match response_receiver.recv() {
-            Ok(response) => match response {
-                Response::Ok200(data) => ((data[0] as u16) << 8) & (data[1] as u16),
-                Response::MemError(err) => panic!("{err:}"),
-                Response::RequestError(err) => panic!("{err:}"),
-                Response::Ok204 => panic!("Error, expected data, received 204"),
-            },
-            Err(err) => panic!("{err:}"),
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BusError::MemError { addr, request, err } => {
+                write!(f, "memory error on {request} at {addr:#06X}: {err}")
+            }
+            BusError::RequestError { addr, request, err } => {
+                write!(f, "invalid {request} at {addr:#06X}: {err}")
+            }
+            BusError::UnexpectedResponse {
+                addr,
+                request,
+                response,
+            } => write!(f, "unexpected {response} to {request} at {addr:#06X}"),
+            BusError::Disconnected { addr, request } => {
+                write!(f, "memory thread gone during {request} at {addr:#06X}")
+            }
         }
     }
+}
 
-    pub fn write_byte(&self, addr: u16, data: u8) {
-        let (request, response_receiver) = Request::create_write_byte_request(addr, data);
-        self.request_sender.send(request).unwrap();
-        match response_receiver.recv() {
-            Ok(response) => match response {
-                Response::Ok200(data) => panic!("Error, expected 204, received 200 with {data:?}"),
-                Response::Ok204 => {}
-                Response::MemError(err) => panic!("{err:}"),
-                Response::RequestError(err) => panic!("{err:}"),
-            },
-            Err(err) => panic!("{err:}"),
+impl RequestInfo {
+    fn describe(&self) -> String {
+        match &self.request_type {
+            RequestType::Read => format!("read of {} bytes", self.request_len),
+            RequestType::Write(data) => format!("write of {data:02X?}"),
+            request_type => format!("{request_type:?} request"),
         }
     }
+}
+
+#[derive(Debug, Clone)]
+pub struct Bus {
+    request_sender: Sender<Request>,
+}
+
+impl Bus {
+    pub fn new(request_sender: Sender<Request>) -> Bus {
+        Bus { request_sender }
+    }
 
-    pub fn load_rom(&self) {
-        let (response_sender, response_receiver) = channel::<Response>();
-        let request = Request {
-            request_info: RequestInfo {
-                addr: 0,
-                request_len: 0,
-                request_type: RequestType::LoadROM,
-            },
-            responder: response_sender,
+    // Sends a request and waits for the response. Returns the data of a 200,
+    // or an empty Vec for a 204 when `expects_data` is false
+    fn send(&self, request_info: RequestInfo, expects_data: bool) -> Result<Vec<u8>, BusError> {
+        let addr = request_info.addr;
+        let request = request_info.describe();
+        let (responder, response_receiver) = channel::<Response>();
+        let message = Request {
+            request_info,
+            responder,
         };
-        self.request_sender.send(request).unwrap();
-        match response_receiver.recv() {
-            Ok(response) => match response {
-                Response::Ok204 => {}
-                Response::Ok200(data) => panic!("Error, expected 204, received 200 with {data:?}"),
-                Response::MemError(err) => panic!("{err:}"),
-                Response::RequestError(err) => panic!("{err:}"),
-            },
-            Err(err) => panic!("{err:}"),
+        if self.request_sender.send(message).is_err() {
+            return Err(BusError::Disconnected { addr, request });
+        }
+        match (response_receiver.recv(), expects_data) {
+            (Ok(Response::Ok200(data)), true) => Ok(data),
+            (Ok(Response::Ok204), false) => Ok(vec![]),
+            (Ok(Response::Ok200(data)), false) => Err(BusError::UnexpectedResponse {
+                addr,
+                request,
+                response: format!("200 with {data:02X?}"),
+            }),
+            (Ok(Response::Ok204), true) => Err(BusError::UnexpectedResponse {
+                addr,
+                request,
+                response: String::from("204"),
+            }),
+            (Ok(Response::MemError(err)), _) => Err(BusError::MemError { addr, request, err }),
+            (Ok(Response::RequestError(err)), _) => {
+                Err(BusError::RequestError { addr, request, err })
+            }
+            (Err(_), _) => Err(BusError::Disconnected { addr, request }),
         }
     }
 
+    fn send_no_content(&self, request_type: RequestType) -> Result<(), BusError> {
+        let request_info = RequestInfo {
+            addr: 0,
+            request_len: 0,
+            request_type,
+        };
+        self.send(request_info, false).map(|_| ())
+    }
+
+    // Reads `len` bytes starting at `addr`, wrapping past 0xFFFF
+    pub fn try_read(&self, addr: u16, len: u8) -> Result<Vec<u8>, BusError> {
+        let request_info = RequestInfo {
+            addr,
+            request_len: len,
+            request_type: RequestType::Read,
+        };
+        self.send(request_info, true)
+    }
+
+    // Writes `data` starting at `addr`, wrapping past 0xFFFF
+    pub fn try_write(&self, addr: u16, data: Vec<u8>) -> Result<(), BusError> {
+        let request_info = RequestInfo {
+            addr,
+            request_len: data.len() as u8,
+            request_type: RequestType::Write(data),
+        };
+        self.send(request_info, false).map(|_| ())
+    }
+
+    pub fn try_read_byte(&self, addr: u16) -> Result<u8, BusError> {
+        Ok(self.try_read(addr, 1)?[0])
+    }
+
+    // Little-endian, as the CPU stores 16-bit values
+    pub fn try_read_word(&self, addr: u16) -> Result<u16, BusError> {
+        let data = self.try_read(addr, 2)?;
+        Ok((data[0] as u16) | ((data[1] as u16) << 8))
+    }
+
+    pub fn try_write_byte(&self, addr: u16, data: u8) -> Result<(), BusError> {
+        self.try_write(addr, vec![data])
+    }
+
+    pub fn try_read_oam(&self) -> Result<Vec<u8>, BusError> {
+        self.try_read(0xFE00, 160)
+    }
+
+    pub fn try_request_interrupt(&self, flag: InterruptFlag) -> Result<(), BusError> {
+        self.send_no_content(RequestType::Interrupt(flag))
+    }
+
+    pub fn try_tick(&self, t: u8) -> Result<(), BusError> {
+        self.send_no_content(RequestType::Tick(t))
+    }
+
+    pub fn try_save(&self) -> Result<(), BusError> {
+        self.send_no_content(RequestType::Save)
+    }
+
+    pub fn try_start_recording(&self, path: PathBuf, stems: bool) -> Result<(), BusError> {
+        self.send_no_content(RequestType::StartRecording(path, stems))
+    }
+
+    pub fn try_stop_recording(&self) -> Result<(), BusError> {
+        self.send_no_content(RequestType::StopRecording)
+    }
+
+    // The methods below panic where their try_ counterparts return an error
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        self.try_read_byte(addr)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn read_word(&self, addr: u16) -> u16 {
+        self.try_read_word(addr)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn write_byte(&self, addr: u16, data: u8) {
+        self.try_write_byte(addr, data)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
     pub fn read_oam(&self) -> Vec<u8> {
-        let (request, response_receiver) = Request::create_read_oam_request();
-        self.request_sender.send(request).unwrap();
-        match response_receiver.recv() {
-            Ok(response) => match response {
-                Response::Ok200(data) => data,
-                Response::Ok204 => panic!("Error, expected data, received 204 instead"),
-                Response::MemError(err) => panic!("{err:}"),
-                Response::RequestError(err) => panic!("{err:}"),
-            },
-            Err(err) => panic!("{err:}"),
-        }
+        self.try_read_oam().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn request_interrupt(&self, flag: InterruptFlag) {
+        self.try_request_interrupt(flag)
+            .unwrap_or_else(|err| panic!("{err}"))
     }
+
+    pub fn tick(&self, t: u8) {
+        self.try_tick(t).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn save(&self) {
+        self.try_save().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn stop_recording(&self) {
+        self.try_stop_recording()
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+}
+
+#[cfg(test)]
+fn serve_one(request_receiver: std::sync::mpsc::Receiver<Request>, response: Response) {
+    std::thread::spawn(move || {
+        let request = request_receiver.recv().unwrap();
+        request.responder.send(response).unwrap();
+    });
+}
+
+#[test]
+fn test_bus_errors() {
+    let (request_sender, request_receiver) = channel();
+    let bus = Bus::new(request_sender);
+    serve_one(
+        request_receiver,
+        Response::RequestError(String::from("bad")),
+    );
+    let err = bus.try_read_word(0xC000).unwrap_err();
+    assert_eq!(err.addr(), 0xC000);
+    assert_eq!(err.to_string(), "invalid read of 2 bytes at 0xC000: bad");
+
+    let (request_sender, request_receiver) = channel();
+    let bus = Bus::new(request_sender);
+    serve_one(request_receiver, Response::Ok200(vec![0]));
+    assert!(matches!(
+        bus.try_write_byte(0xFF80, 1),
+        Err(BusError::UnexpectedResponse { addr: 0xFF80, .. })
+    ));
+
+    let (request_sender, request_receiver) = channel();
+    let bus = Bus::new(request_sender);
+    drop(request_receiver);
+    assert_eq!(
+        bus.try_read_byte(0x1234),
+        Err(BusError::Disconnected {
+            addr: 0x1234,
+            request: String::from("read of 1 bytes")
+        })
+    );
 }
 
 // ReadByte (I: u8 O: u8)
@@ -0,0 +1,161 @@
+/*
+
+Timer
+
+0xFF04: DIV - Upper 8 bits of the internal 16-bit counter, any write resets
+        the whole counter
+0xFF05: TIMA - Timer counter
+0xFF06: TMA - Value TIMA is reloaded with after overflowing
+0xFF07: TAC - Bit 2: Enable
+              Bit 1-0: Clock select
+                00: 4096 Hz   (counter bit 9)
+                01: 262144 Hz (counter bit 3)
+                10: 65536 Hz  (counter bit 5)
+                11: 16384 Hz  (counter bit 7)
+
+TIMA increments on the falling edge of (enable AND selected counter bit), so
+writing DIV or TAC can increment it early. After an overflow TIMA reads 0 for
+one m-cycle before TMA is loaded and the interrupt is requested.
+
+*/
+
+pub const DIV_ADDR: u16 = 0xFF04;
+pub const TIMA_ADDR: u16 = 0xFF05;
+pub const TMA_ADDR: u16 = 0xFF06;
+pub const TAC_ADDR: u16 = 0xFF07;
+
+#[derive(Debug, Default)]
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // Set for the m-cycle between TIMA overflowing and being reloaded
+    reload_pending: bool,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer::default()
+    }
+
+    // Internal counter value when the DMG boot ROM jumps to 0x0100
+    pub fn skip_boot(&mut self) {
+        self.counter = 0xABCC;
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            DIV_ADDR => (self.counter >> 8) as u8,
+            TIMA_ADDR => self.tima,
+            TMA_ADDR => self.tma,
+            // Unused TAC bits read as 1
+            _ => self.tac | 0xF8,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        let signal = self.signal();
+        match addr {
+            DIV_ADDR => self.counter = 0,
+            TIMA_ADDR => {
+                // Writing TIMA during the reload m-cycle cancels the reload
+                self.tima = value;
+                self.reload_pending = false;
+            }
+            TMA_ADDR => self.tma = value,
+            _ => self.tac = value & 0x07,
+        }
+        if signal && !self.signal() {
+            self.increment();
+        }
+    }
+
+    // Advances the timer by `t` t-cycles; returns true if the timer interrupt
+    // should be requested
+    pub fn tick(&mut self, t: u8) -> bool {
+        let mut interrupt = false;
+        for _ in 0..t / 4 {
+            if self.reload_pending {
+                self.reload_pending = false;
+                self.tima = self.tma;
+                interrupt = true;
+            }
+
+            let signal = self.signal();
+            self.counter = self.counter.wrapping_add(4);
+            if signal && !self.signal() {
+                self.increment();
+            }
+        }
+        interrupt
+    }
+
+    // DIV bit 4; its falling edge steps the APU frame sequencer
+    pub fn div_apu_bit(&self) -> bool {
+        (self.counter >> 12) & 1 == 1
+    }
+
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            _ => 7,
+        };
+        (self.tac >> 2) & 1 == 1 && (self.counter >> bit) & 1 == 1
+    }
+
+    fn increment(&mut self) {
+        let (tima, did_overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        self.reload_pending = did_overflow;
+    }
+}
+
+#[test]
+fn test_timer_rate() {
+    let mut timer = Timer::new();
+    // 262144 Hz: every 16 t-cycles
+    timer.write(TAC_ADDR, 0b101);
+    timer.tick(64);
+    assert_eq!(timer.read(TIMA_ADDR), 4);
+
+    // DIV increments every 256 t-cycles
+    for _ in 0..3 {
+        timer.tick(64);
+    }
+    assert_eq!(timer.read(DIV_ADDR), 1);
+}
+
+#[test]
+fn test_timer_overflow_reload() {
+    let mut timer = Timer::new();
+    timer.write(TAC_ADDR, 0b101);
+    timer.write(TMA_ADDR, 0xF0);
+    timer.write(TIMA_ADDR, 0xFF);
+
+    assert!(!timer.tick(16));
+    // TIMA reads 0 for one m-cycle before the reload
+    assert_eq!(timer.read(TIMA_ADDR), 0);
+    assert!(timer.tick(4));
+    assert_eq!(timer.read(TIMA_ADDR), 0xF0);
+}
+
+#[test]
+fn test_timer_div_write_glitch() {
+    let mut timer = Timer::new();
+    timer.write(TAC_ADDR, 0b101);
+    timer.tick(8);
+    assert_eq!(timer.read(TIMA_ADDR), 0);
+
+    // Bit 3 of the counter is set, resetting it is a falling edge
+    timer.write(DIV_ADDR, 0x12);
+    assert_eq!(timer.read(TIMA_ADDR), 1);
+    assert_eq!(timer.read(DIV_ADDR), 0);
+
+    // Disabling the timer while the bit is set is also a falling edge
+    timer.tick(8);
+    timer.write(TAC_ADDR, 0b001);
+    assert_eq!(timer.read(TIMA_ADDR), 2);
+}